@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::color::Color;
+use crate::exr::{self, ExrChannel};
+use crate::framebuffer::Framebuffer;
+use crate::image::Image;
+use crate::vector::{Point, Vector};
+
+// Arbitrary output variables of a single camera sample. Surface attributes
+// are those of the first hit, radiance is split by the number of bounces
+// before it reached the camera.
+#[derive(Clone, Copy)]
+pub struct Aovs {
+    pub albedo: Color,
+    pub normal: Vector,
+    // Distance from the camera, infinite where the path escaped.
+    pub depth: f64,
+    pub position: Point,
+    // Index of the scene object, 0 for the background.
+    pub object_id: u32,
+    // Identity of the material, 0 for the background.
+    pub material_id: usize,
+    // Emitters and background seen directly.
+    pub emission: Color,
+    // Light arriving after a single bounce.
+    pub direct: Color,
+    // Light arriving after two or more bounces.
+    pub indirect: Color,
+}
+
+impl Default for Aovs {
+    fn default() -> Self {
+        Aovs {
+            albedo: Color::zero(),
+            normal: Vector::zero(),
+            depth: f64::INFINITY,
+            position: Point::zero(),
+            object_id: 0,
+            material_id: 0,
+            emission: Color::zero(),
+            direct: Color::zero(),
+            indirect: Color::zero(),
+        }
+    }
+}
+
+impl Aovs {
+    // Adds radiance that reached the camera after `bounces` scatter events.
+    pub fn add_radiance(&mut self, bounces: u32, radiance: Color) {
+        match bounces {
+            0 => self.emission += radiance,
+            1 => self.direct += radiance,
+            _ => self.indirect += radiance,
+        }
+    }
+}
+
+// Buffers that can be written next to the beauty image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+    Depth,
+    Position,
+    Normal,
+    Albedo,
+    ObjectId,
+    MaterialId,
+    Direct,
+    Indirect,
+    Emission,
+}
+
+impl Aov {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+        }
+    }
+
+    fn channel_names(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Position | Aov::Normal => &["X", "Y", "Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect | Aov::Emission => &["R", "G", "B"],
+        }
+    }
+
+    // Channels of the buffer, named `prefix` + channel.
+    fn channels(self, framebuffer: &Framebuffer, prefix: &str) -> Vec<ExrChannel> {
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        let names = self.channel_names();
+        let mut data = vec![Vec::with_capacity(width * height); names.len()];
+        // Material identities are numbered in order of appearance.
+        let mut materials: HashMap<usize, usize> = HashMap::new();
+        for y in 0..height {
+            for x in 0..width {
+                let values = match self {
+                    Aov::Depth => Vector::new(framebuffer.depth(x, y), 0.0, 0.0),
+                    Aov::Position => framebuffer.position(x, y),
+                    Aov::Normal => framebuffer.normal(x, y),
+                    Aov::Albedo => framebuffer.albedo(x, y),
+                    Aov::ObjectId => Vector::new(framebuffer.object_id(x, y) as f64, 0.0, 0.0),
+                    Aov::MaterialId => {
+                        let material = framebuffer.material_id(x, y);
+                        let id = if material == 0 {
+                            0
+                        } else {
+                            let next = materials.len() + 1;
+                            *materials.entry(material).or_insert(next)
+                        };
+                        Vector::new(id as f64, 0.0, 0.0)
+                    }
+                    Aov::Direct => framebuffer.direct(x, y),
+                    Aov::Indirect => framebuffer.indirect(x, y),
+                    Aov::Emission => framebuffer.emission(x, y),
+                };
+                for (c, channel) in data.iter_mut().enumerate() {
+                    channel.push(values[c] as f32);
+                }
+            }
+        }
+        names
+            .iter()
+            .zip(data)
+            .map(|(name, data)| ExrChannel::new(format!("{}{}", prefix, name), data))
+            .collect()
+    }
+}
+
+pub enum AovOutput {
+    // One EXR per buffer, named <path>.<aov>.exr.
+    Files(PathBuf),
+    // A single EXR with the beauty image and every buffer as a layer.
+    Layers(PathBuf),
+}
+
+pub fn write_aovs(
+    output: &AovOutput,
+    aovs: &[Aov],
+    framebuffer: &Framebuffer,
+    beauty: &Image,
+) -> io::Result<()> {
+    let (width, height) = (framebuffer.width(), framebuffer.height());
+    match output {
+        AovOutput::Files(path) => {
+            for aov in aovs {
+                let channels = aov.channels(framebuffer, "");
+                exr::save_exr(aov_path(path, aov.name()), width, height, &channels)?;
+            }
+        }
+        AovOutput::Layers(path) => {
+            let mut channels = Vec::new();
+            let mut rgb = [Vec::new(), Vec::new(), Vec::new()];
+            for y in 0..height {
+                for x in 0..width {
+                    let c = beauty.pixel(x, y);
+                    for (i, channel) in rgb.iter_mut().enumerate() {
+                        channel.push(c[i] as f32);
+                    }
+                }
+            }
+            for (name, data) in ["R", "G", "B"].iter().zip(rgb) {
+                channels.push(ExrChannel::new(*name, data));
+            }
+            for aov in aovs {
+                channels.extend(aov.channels(framebuffer, &format!("{}.", aov.name())));
+            }
+            exr::save_exr(path, width, height, &channels)?;
+        }
+    }
+    Ok(())
+}
+
+fn aov_path(path: &Path, name: &str) -> PathBuf {
+    let mut file: OsString = path.as_os_str().to_owned();
+    file.push(format!(".{}.exr", name));
+    PathBuf::from(file)
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::color;
+use crate::image::Image;
+use crate::rtweeknd::{self, PI};
+use crate::sampler;
+
+// Shape of the lens opening, which is the shape of out of focus highlights
+// (bokeh). Positions are on the unit disk scaled by the defocus radius.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    // Regular polygon formed by `blades` straight diaphragm blades, rotated
+    // by `rotation` degrees.
+    Polygon { blades: u32, rotation: f64 },
+    // Grayscale image over the square around the unit disk, its brightness
+    // is the transmission of the opening.
+    Mask(Rc<ApertureMask>),
+}
+
+impl Aperture {
+    // Point on the aperture, with density proportional to its transmission.
+    pub fn sample(&self, u: (f64, f64)) -> (f64, f64) {
+        match self {
+            Aperture::Circle => {
+                let p = sampler::concentric_disk(u);
+                (p.x(), p.y())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                // Pick one of the triangles fanning out from the center and
+                // reuse the rest of the random number inside it.
+                let scaled = u.0 * blades as f64;
+                let k = (scaled as u32).min(blades - 1);
+                let s = (scaled - k as f64).sqrt();
+                let t = u.1;
+                let angle =
+                    |i: u32| rtweeknd::deg2rad(*rotation) + 2.0 * PI * i as f64 / blades as f64;
+                let (a0, a1) = (angle(k), angle(k + 1));
+                (
+                    s * ((1.0 - t) * a0.cos() + t * a1.cos()),
+                    s * ((1.0 - t) * a0.sin() + t * a1.sin()),
+                )
+            }
+            Aperture::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+// Tabulated aperture image, sampled in proportion to its brightness.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // Cumulative distribution over rows, and over the pixels of each row.
+    rows: Vec<f64>,
+    columns: Vec<Vec<f64>>,
+}
+
+impl ApertureMask {
+    pub fn new(image: &Image) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let mut rows = vec![0.0; height + 1];
+        let mut columns = Vec::with_capacity(height);
+        for y in 0..height {
+            let mut cdf = vec![0.0; width + 1];
+            for x in 0..width {
+                let value = color::luminance(image.pixel(x, y)).max(0.0);
+                cdf[x + 1] = cdf[x] + value;
+            }
+            rows[y + 1] = rows[y] + cdf[width];
+            columns.push(cdf);
+        }
+        ApertureMask {
+            width,
+            height,
+            rows,
+            columns,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(ApertureMask::new(&Image::load(path)?))
+    }
+
+    fn sample(&self, u: (f64, f64)) -> (f64, f64) {
+        if self.height == 0 || self.rows[self.height] <= 0.0 {
+            // An empty or black mask, fall back to a pinhole.
+            return (0.0, 0.0);
+        }
+        let y = sample_cdf(&self.rows, u.1);
+        let row = (y as usize).min(self.height - 1);
+        let x = sample_cdf(&self.columns[row], u.0);
+        // Image rows go down, the aperture v axis up.
+        (
+            2.0 * x / self.width as f64 - 1.0,
+            1.0 - 2.0 * y / self.height as f64,
+        )
+    }
+}
+
+// Continuous sample of a piecewise constant distribution given by its
+// unnormalized cumulative sums, as a position in [0, n).
+fn sample_cdf(cdf: &[f64], u: f64) -> f64 {
+    let n = cdf.len() - 1;
+    let target = u * cdf[n];
+    let i = cdf.partition_point(|&c| c <= target).clamp(1, n) - 1;
+    let width = cdf[i + 1] - cdf[i];
+    let offset = if width > 0.0 {
+        (target - cdf[i]) / width
+    } else {
+        0.5
+    };
+    i as f64 + offset.clamp(0.0, 1.0)
+}
@@ -1,35 +1,148 @@
+use crate::aov::{self, Aov, AovOutput, Aovs};
+use crate::aperture::Aperture;
 use crate::color::{self, Color};
+use crate::colorspace::{self, ColorSpace, Matrix};
+use crate::denoise::Denoiser;
+use crate::exposure::{self, AutoExposure};
+use crate::filter::Filter;
+use crate::framebuffer::Framebuffer;
 use crate::hittable::{HitRecord, Hittable};
+use crate::image::Image;
 use crate::interval::Interval;
+use crate::lens::LensSystem;
+use crate::light::Light;
+use crate::material::{Lobe, Material};
+use crate::medium::MediumStack;
+use crate::onb::Onb;
+use crate::projection::Projection;
 use crate::ray::Ray;
 use crate::rtweeknd::{self, INF};
+use crate::sampler::{self, Sampler, SamplerType};
+use crate::sky::Background;
+use crate::spectrum;
+use crate::tonemap::{Lut, ToneMapper};
 use crate::vector::{Point, Vector};
 
-use std::io::{self, Write};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
 
 pub struct Camera {
     pub aspect_ratio: f64,
     pub image_width: u32,
     pub max_depth: u32,
+    // Limits on scatter events of each kind along a path. They default to
+    // unlimited, so only max_depth applies.
+    pub max_diffuse_depth: u32,
+    pub max_glossy_depth: u32,
+    pub max_transmission_depth: u32,
+    // Scattering events inside participating media, e.g. subsurface walks.
+    pub max_volume_depth: u32,
+    // Surfaces a path may pass straight through, transparent parts of
+    // cutouts and boundaries inside higher priority media. These do not
+    // count as depth, so this keeps dense stacks of them from looping.
+    pub max_pass_through: u32,
+    // Bounce after which paths are terminated by Russian roulette.
+    pub russian_roulette_depth: u32,
+    pub projection: Projection,
+    // Vertical field of view of the perspective projection, in degrees.
     pub vfov: f64,
     pub lookfrom: Point,
     pub lookat: Point,
     pub vup: Vector,
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    // Shape of the lens opening, scaled to the defocus disk.
+    pub aperture: Aperture,
+    // Offset of the lens barrel at the image corners in units of the
+    // aperture radius. 0 disables optical vignetting, around 1 gives
+    // pronounced cat's eye bokeh.
+    pub optical_vignetting: f64,
+    // Horizontal squeeze of anamorphic lenses, e.g. 1.33 or 2. 1 is a
+    // spherical lens. The lens covers a field as many times wider on the
+    // same film, and the image is written desqueezed: aspect_ratio is that
+    // of the film, and the image is squeeze times wider than it.
+    pub anamorphic_squeeze: f64,
+    // Trace rays through a real lens instead. It is focused at focus_dist
+    // from the film at lookfrom, and takes the place of the projection,
+    // field of view and the thin lens settings above.
+    pub lens: Option<LensSystem>,
+    // Photographic controls. The f-number sets the aperture from the focal
+    // length in place of defocus_angle, and the shutter time is how long
+    // moving objects travel during the exposure. Brightness scales with
+    // shutter * iso / f_number^2 relative to f/1, 1 s and ISO 100, which
+    // also stand in for unset values.
+    pub f_number: Option<f64>,
+    pub shutter: Option<f64>,
+    pub iso: f64,
+    // In stops, applied on top of the manual or the auto exposure.
+    pub exposure_compensation: f64,
+    // Meter the brightness from the image instead of the settings above.
+    pub auto_exposure: Option<AutoExposure>,
+    // Space the scene's colors are given and rendered in. Colors of the sky
+    // and of spectral samples are converted into it.
+    pub working_space: ColorSpace,
+    // Color temperature in kelvin of the light that is rendered white, e.g.
+    // 3200 for tungsten. None keeps the white of the working space.
+    pub white_balance: Option<f64>,
+    // Primaries and encoding of the written image.
+    pub display_space: ColorSpace,
+    // Display transform of the written image: the tone mapper takes the
+    // exposed linear image to the display range, and the look adjusts the
+    // result. AOV files keep the linear image.
+    pub tone_mapper: ToneMapper,
+    pub look: Option<Rc<Lut>>,
+    // Film height the vertical field of view is measured on, in mm with
+    // scene units in meters. It only sets the focal length for f_number.
+    pub sensor_height: f64,
+    pub background: Background,
+    // Analytic lights. Their colors are in linear sRGB and are converted to
+    // the working space.
+    pub lights: Vec<Light>,
+    // Trace a single wavelength per sample instead of RGB.
+    pub spectral: bool,
+    // Progressive rendering: samples added to every pixel per pass, and
+    // optional early stops once the time budget is spent or the estimated
+    // noise drops below the threshold.
+    pub samples_per_pass: u32,
+    pub time_budget: Option<Duration>,
+    pub noise_threshold: Option<f64>,
+    // Where to write the image after every pass.
+    pub progress_path: Option<PathBuf>,
+    // Extra buffers for compositing and where to write them.
+    pub aovs: Vec<Aov>,
+    pub aov_output: Option<AovOutput>,
+    // Post-process applied to the final image, guided by first hit albedo,
+    // normal and depth.
+    pub denoiser: Option<Denoiser>,
+    // Reconstruction filter the samples are splatted onto the image with.
+    pub filter: Filter,
+    // Sequence the random numbers of each pixel sample are drawn from, and a
+    // seed to decorrelate renders, e.g. the frames of an animation.
+    pub sampler: SamplerType,
+    pub seed: u32,
+    // Adaptive sampling: a pixel stops receiving samples once its relative
+    // standard error is below the threshold. samples_per_pixel is the
+    // maximum per pixel.
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples_per_pixel: u32,
     samples_per_pixel: u32,
-    pixel_samples_scale: f64,
     image_height: u32,
     center: Point,
-    pixel00_loc: Point,
-    pixel_delta_u: Vector,
-    pixel_delta_v: Vector,
     // camera frame basis vector
     u: Vector,
     v: Vector,
     w: Vector,
     defocus_disk_u: Vector,
     defocus_disk_v: Vector,
+    srgb_to_working: Matrix,
+    working_to_srgb: Matrix,
+    // The lights and the sky's sun in the working space.
+    working_lights: Vec<Light>,
+    sun: Option<Light>,
 }
 
 impl Camera {
@@ -38,118 +151,794 @@ impl Camera {
             aspect_ratio,
             image_width,
             max_depth: 10,
+            max_diffuse_depth: u32::MAX,
+            max_glossy_depth: u32::MAX,
+            max_transmission_depth: u32::MAX,
+            max_volume_depth: u32::MAX,
+            max_pass_through: 256,
+            russian_roulette_depth: 3,
+            projection: Projection::Perspective,
             vfov,
             lookfrom: Vector::zero(),
             lookat: Point::new(0.0, 0.0, -1.0),
             vup: Vector::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            aperture: Aperture::Circle,
+            optical_vignetting: 0.0,
+            anamorphic_squeeze: 1.0,
+            lens: None,
+            f_number: None,
+            shutter: None,
+            iso: 100.0,
+            exposure_compensation: 0.0,
+            auto_exposure: None,
+            sensor_height: 24.0,
+            working_space: ColorSpace::default(),
+            white_balance: None,
+            display_space: ColorSpace::default(),
+            tone_mapper: ToneMapper::default(),
+            look: None,
+            background: Background::Gradient,
+            lights: Vec::new(),
+            spectral: false,
+            samples_per_pass: 4,
+            time_budget: None,
+            noise_threshold: None,
+            progress_path: None,
+            aovs: Vec::new(),
+            aov_output: None,
+            denoiser: None,
+            filter: Filter::default(),
+            sampler: SamplerType::Sobol,
+            seed: 0,
+            adaptive_threshold: None,
+            min_samples_per_pixel: 16,
             samples_per_pixel,
-            pixel_samples_scale: 1.0 / 10.0,
             image_height: ((image_width as f64 / aspect_ratio) as u32).max(1),
             center: Point::new(0.0, 0.0, 0.0),
-            pixel00_loc: Point::new(0.0, 0.0, 0.0),
-            pixel_delta_u: Vector::new(0.0, 0.0, 0.0),
-            pixel_delta_v: Vector::new(0.0, 0.0, 0.0),
             u: Vector::zero(),
             v: Vector::zero(),
             w: Vector::zero(),
             defocus_disk_u: Vector::zero(),
             defocus_disk_v: Vector::zero(),
+            srgb_to_working: IDENTITY,
+            working_to_srgb: IDENTITY,
+            working_lights: Vec::new(),
+            sun: None,
         }
     }
     pub fn initialize(&mut self) {
         self.center = self.lookfrom;
-
-        self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
-
-        let theta = rtweeknd::deg2rad(self.vfov);
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * self.focus_dist;
-        let viewport_width = viewport_height * (self.image_width as f64 / self.image_height as f64);
+        let squeeze = self.anamorphic_squeeze.max(1e-3);
+        self.image_height =
+            ((self.image_width as f64 / (self.aspect_ratio * squeeze)) as u32).max(1);
 
         // basis vector
         self.w = (self.lookfrom - self.lookat).unit();
         self.u = Vector::cross(self.vup, self.w).unit();
         self.v = Vector::cross(self.w, self.u);
 
-        let viewport_u = viewport_width * self.u;
-        let viewport_v = viewport_height * (-self.v);
-
-        self.pixel_delta_u = viewport_u / self.image_width as f64;
-        self.pixel_delta_v = viewport_v / self.image_height as f64;
-
-        let viewport_upper_left =
-            self.center - self.focus_dist * self.w - viewport_v / 2.0 - viewport_u / 2.0;
-        self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
-
-        let defocus_radius = self.focus_dist * (rtweeknd::deg2rad(self.defocus_angle / 2.0)).tan();
+        let defocus_radius = match self.f_number {
+            Some(f_number) => {
+                // Aperture diameter is the focal length over the f-number.
+                let focal_length =
+                    0.5 * self.sensor_height / (rtweeknd::deg2rad(self.vfov) / 2.0).tan() * 0.001;
+                focal_length / f_number / 2.0
+            }
+            None => self.focus_dist * (rtweeknd::deg2rad(self.defocus_angle / 2.0)).tan(),
+        };
         self.defocus_disk_u = self.u * defocus_radius;
         self.defocus_disk_v = self.v * defocus_radius;
+
+        self.srgb_to_working = ColorSpace::Srgb.conversion(self.working_space);
+        self.working_to_srgb = self.working_space.conversion(ColorSpace::Srgb);
+        self.working_lights = self
+            .lights
+            .iter()
+            .map(|light| light.clone().in_space(self.working_space))
+            .collect();
+        self.sun = self
+            .background
+            .sun_light()
+            .map(|sun| sun.in_space(self.working_space));
+
+        if let Some(lens) = &mut self.lens {
+            if let Some(f_number) = self.f_number {
+                lens.set_f_number(f_number);
+            }
+            lens.focus(self.focus_dist);
+        }
     }
     pub fn render<T: Hittable>(&self, world: &T) {
-        println!("P3");
-        println!("{} {}", self.image_width, self.image_height);
-        println!("255");
-
-        for j in 0..self.image_height {
-            eprint!("\rScanlines remaining: {} ", self.image_height - j);
-            io::stderr().flush().unwrap();
-            for i in 0..self.image_width {
-                let mut color_pixel = Color::zero();
-                for _ in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    color_pixel += self.ray_color(r, self.max_depth, world);
+        let mut framebuffer =
+            Framebuffer::new(self.image_width as usize, self.image_height as usize)
+                .with_filter(self.filter)
+                .with_space(self.working_space);
+        let start = Instant::now();
+        let mut sampler = self.sampler.create(self.samples_per_pixel, self.seed);
+        let mut pass = 0;
+
+        // Every pass refines the whole image so it can be stopped at any time.
+        loop {
+            let plan = self.plan_pass(&framebuffer);
+            let total: u64 = plan.iter().map(|&n| n as u64).sum();
+            if total == 0 {
+                break;
+            }
+            pass += 1;
+            for j in 0..self.image_height {
+                eprint!(
+                    "\rPass {} ({} samples): scanlines remaining: {} ",
+                    pass,
+                    total,
+                    self.image_height - j
+                );
+                io::stderr().flush().unwrap();
+                for i in 0..self.image_width {
+                    let count = plan[(j * self.image_width + i) as usize];
+                    for _ in 0..count {
+                        let index = framebuffer.samples(i as usize, j as usize);
+                        sampler.start_pixel_sample(i, j, index);
+                        let offset = self.sample_square(sampler.get_2d());
+                        let mut aovs = Aovs::default();
+                        let sample =
+                            self.sample_pixel(i, j, offset, world, sampler.as_mut(), &mut aovs);
+                        framebuffer.add_sample(
+                            i as usize,
+                            j as usize,
+                            (offset.x(), offset.y()),
+                            sample,
+                            &aovs,
+                        );
+                    }
                 }
-                color::write_color(&mut io::stdout(), color_pixel * self.pixel_samples_scale)
-                    .unwrap();
+            }
+
+            if let Some(path) = &self.progress_path {
+                let mut image = framebuffer.to_image();
+                self.expose(&mut image);
+                if let Err(err) = self.display(&image).save_ppm(path) {
+                    eprintln!("\nWarning: could not write {}: {}", path.display(), err);
+                }
+            }
+            if self
+                .time_budget
+                .is_some_and(|budget| start.elapsed() >= budget)
+            {
+                break;
+            }
+            if self
+                .noise_threshold
+                .is_some_and(|threshold| framebuffer.noise() <= threshold)
+            {
+                break;
             }
         }
-        eprintln!("\nDone!")
-    }
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let offset = self.sample_square();
-        let pixel_sample = self.pixel00_loc
-            + ((i as f64 + offset.x()) * self.pixel_delta_u)
-            + ((j as f64 + offset.y()) * self.pixel_delta_v);
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
-        } else {
-            self.defocus_disk_sample()
+        eprintln!(
+            "\nDone! {:.1} spp on average in {:.1}s, noise {:.4}",
+            framebuffer.average_samples(),
+            start.elapsed().as_secs_f64(),
+            framebuffer.noise()
+        );
+
+        let mut image = match &self.denoiser {
+            Some(denoiser) => denoiser.denoise(&framebuffer),
+            None => framebuffer.to_image(),
         };
-        Ray::new(ray_origin, pixel_sample - self.center)
+        self.expose(&mut image);
+        let mut out = BufWriter::new(io::stdout().lock());
+        self.display(&image).write_ppm(&mut out).unwrap();
+        out.flush().unwrap();
+
+        if let Some(output) = &self.aov_output {
+            if let Err(err) = aov::write_aovs(output, &self.aovs, &framebuffer, &image) {
+                eprintln!("Warning: could not write the AOVs: {}", err);
+            }
+        }
     }
-    fn defocus_disk_sample(&self) -> Point {
-        let p = Vector::random_in_unit_disk();
-        self.center + (p[0] * self.defocus_disk_u) + (p[1] * self.defocus_disk_v)
+    // Scales the linear image by the photographic exposure, or with auto
+    // exposure by what metering the image itself gives, and white balances
+    // it.
+    fn expose(&self, image: &mut Image) {
+        let scale = match &self.auto_exposure {
+            Some(auto_exposure) => auto_exposure.exposure(image, self.working_space),
+            None => exposure::exposure(
+                self.f_number.unwrap_or(1.0),
+                self.shutter.unwrap_or(1.0),
+                self.iso,
+            ),
+        } * self.exposure_compensation.exp2();
+        if scale != 1.0 {
+            image.map(|c| scale * c);
+        }
+        if let Some(kelvin) = self.white_balance {
+            let m = self.working_space.white_balance(kelvin);
+            image.map(|c| colorspace::mul(&m, c));
+        }
     }
-    fn sample_square(&self) -> Vector {
-        Vector::new(rtweeknd::random() - 0.5, rtweeknd::random() - 0.5, 0.0)
+    // Display referred copy of the exposed image. The tone mappers' curves
+    // and matrices are made for Rec. 709 primaries, so they run on linear
+    // sRGB and the result is converted to the display's primaries.
+    fn display(&self, image: &Image) -> Image {
+        let to_display = (self.display_space != ColorSpace::Srgb)
+            .then(|| ColorSpace::Srgb.conversion(self.display_space));
+        let mut display = image.clone();
+        display.map(|c| {
+            let c = self.tone_mapper.apply(self.to_srgb(c));
+            let c = match &to_display {
+                Some(m) => colorspace::mul(m, c),
+                None => c,
+            };
+            if self.display_space == ColorSpace::Srgb && self.look.is_none() {
+                return c;
+            }
+            let encode = |x: f64| self.display_space.encode(x.clamp(0.0, 1.0));
+            let mut encoded = Color::new(encode(c.x()), encode(c.y()), encode(c.z()));
+            if let Some(look) = &self.look {
+                encoded = look.apply(encoded);
+            }
+            // The PPM writer applies the sRGB curve, so hand it the values
+            // that it encodes back into the display's code values.
+            Color::new(
+                color::srgb_to_linear(encoded.x()),
+                color::srgb_to_linear(encoded.y()),
+                color::srgb_to_linear(encoded.z()),
+            )
+        });
+        display
     }
-    fn ray_color<T: Hittable>(&self, r: Ray, depth: u32, world: &T) -> Color {
-        if depth <= 0 {
+    // Number of samples each pixel gets in the next pass. With adaptive
+    // sampling, converged pixels are skipped and the others get samples in
+    // proportion to their estimated error.
+    fn plan_pass(&self, framebuffer: &Framebuffer) -> Vec<u32> {
+        let samples_per_pass = self.samples_per_pass.max(1);
+        let width = self.image_width as usize;
+        let height = self.image_height as usize;
+        let mut plan = vec![0; width * height];
+
+        let Some(threshold) = self.adaptive_threshold else {
+            for y in 0..height {
+                for x in 0..width {
+                    let remaining = self.samples_per_pixel - framebuffer.samples(x, y);
+                    plan[y * width + x] = samples_per_pass.min(remaining);
+                }
+            }
+            return plan;
+        };
+
+        let min_samples = self.min_samples_per_pixel.min(self.samples_per_pixel);
+        let mut errors = vec![0.0; width * height];
+        let mut error_sum = 0.0;
+        let mut active = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let n = framebuffer.samples(x, y);
+                if n >= self.samples_per_pixel {
+                    continue;
+                }
+                let error = framebuffer.relative_error(x, y);
+                if n < min_samples {
+                    // Not enough samples to trust the estimate yet.
+                    plan[y * width + x] = samples_per_pass.min(min_samples - n).max(1);
+                } else if error > threshold {
+                    errors[y * width + x] = error;
+                    error_sum += error;
+                    active += 1;
+                }
+            }
+        }
+        if active > 0 {
+            let mean_error = error_sum / active as f64;
+            for (i, &error) in errors.iter().enumerate() {
+                if error <= 0.0 {
+                    continue;
+                }
+                let share = (samples_per_pass as f64 * error / mean_error).round() as u32;
+                let remaining = self.samples_per_pixel - framebuffer.samples(i % width, i / width);
+                plan[i] = share.clamp(1, 4 * samples_per_pass).min(remaining);
+            }
+        }
+        plan
+    }
+    fn sample_pixel<T: Hittable>(
+        &self,
+        i: u32,
+        j: u32,
+        offset: Vector,
+        world: &T,
+        sampler: &mut dyn Sampler,
+        aovs: &mut Aovs,
+    ) -> Color {
+        // The wavelength is picked first, lenses refract each differently.
+        let lambda = self
+            .spectral
+            .then(|| spectrum::sample_wavelength(sampler.get_1d()));
+        let Some((mut r, weight)) = self.get_ray(i, j, offset, lambda, sampler) else {
             return Color::zero();
+        };
+        r.set_wavelength(lambda);
+        if let Some(shutter) = self.shutter {
+            r.set_time(sampler.get_1d() * shutter);
         }
-        let mut rec = HitRecord::new();
-        if world.hit(&r, Interval::new(0.001, INF), &mut rec) {
-            let mut scattered = Ray::new(Point::zero(), Vector::zero());
-            let mut attenuation = Color::zero();
-            if let Some(mat) = rec.material.clone() {
-                // Check if material scatters ray
-                if mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
-                    return attenuation * self.ray_color(scattered, depth - 1, world);
+        let radiance = self.ray_color(r, world, sampler, aovs);
+        let (radiance, emission, direct, indirect) = match lambda {
+            Some(lambda) => (
+                self.to_working(spectrum::to_rgb(radiance.x(), lambda)),
+                self.to_working(spectrum::to_rgb(aovs.emission.x(), lambda)),
+                self.to_working(spectrum::to_rgb(aovs.direct.x(), lambda)),
+                self.to_working(spectrum::to_rgb(aovs.indirect.x(), lambda)),
+            ),
+            None => (radiance, aovs.emission, aovs.direct, aovs.indirect),
+        };
+        aovs.emission = weight * emission;
+        aovs.direct = weight * direct;
+        aovs.indirect = weight * indirect;
+        weight * radiance
+    }
+    // Camera ray and its weight on the film. None where the projection does
+    // not cover the image or the lens blocks the ray.
+    fn get_ray(
+        &self,
+        i: u32,
+        j: u32,
+        offset: Vector,
+        lambda: Option<f64>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, f64)> {
+        let lens = sampler.get_2d();
+        let x = (i as f64 + 0.5 + offset.x()) / self.image_width as f64;
+        let y = (j as f64 + 0.5 + offset.y()) / self.image_height as f64;
+        let aspect = self.image_width as f64 / self.image_height as f64;
+        if let Some(system) = &self.lens {
+            let (origin, direction, weight) = system.camera_ray(x, y, aspect, lens, lambda)?;
+            let ray = Ray::new(
+                self.center + self.to_world(origin),
+                self.to_world(direction),
+            );
+            return Some((ray, weight));
+        }
+
+        let (origin, direction) = self.projection.camera_ray(x, y, aspect, self.vfov)?;
+        let origin = self.center + self.to_world(origin);
+        let direction = self.to_world(direction);
+        // Pinhole unless defocus_angle or f_number open up the lens.
+        if self.defocus_disk_u.near_zero() {
+            return Some((Ray::new(origin, direction), 1.0));
+        }
+
+        // Thin lens: rays through the lens converge on the focus plane, or
+        // at the focus distance for panoramic projections.
+        let (focus, lens_u, lens_v) = match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => {
+                let t = self.focus_dist / Vector::dot(direction, -self.w);
+                (
+                    origin + t * direction,
+                    self.defocus_disk_u,
+                    self.defocus_disk_v,
+                )
+            }
+            _ => {
+                let frame = Onb::new(direction);
+                let radius = self.defocus_disk_u.length();
+                (
+                    origin + self.focus_dist * direction.unit(),
+                    radius * frame.u(),
+                    radius * frame.v(),
+                )
+            }
+        };
+        let (lx, ly) = self.sample_lens(lens, x, y)?;
+        let lens_point = origin + lx * lens_u + ly * lens_v;
+        Some((Ray::new(lens_point, focus - lens_point), 1.0))
+    }
+    // Point on the unit lens for film position (x, y), or None if the lens
+    // barrel blocks it.
+    fn sample_lens(&self, u: (f64, f64), x: f64, y: f64) -> Option<(f64, f64)> {
+        let (lx, ly) = self.aperture.sample(u);
+        // Anamorphic lenses squeeze the image horizontally, which stretches
+        // the bokeh vertically once the image is desqueezed.
+        let lx = lx / self.anamorphic_squeeze.max(1e-3);
+        if self.optical_vignetting > 0.0 {
+            // The barrel is a second unit circle that shifts away from the
+            // aperture towards the image edges, cutting the bokeh into a
+            // cat's eye. Film position is scaled so the corners are at 1.
+            let aspect = self.image_width as f64 / self.image_height as f64;
+            let diagonal = (aspect * aspect + 1.0).sqrt();
+            let fx = (2.0 * x - 1.0) * aspect / diagonal;
+            let fy = (1.0 - 2.0 * y) / diagonal;
+            let (cx, cy) = (self.optical_vignetting * fx, self.optical_vignetting * fy);
+            if (lx - cx).powi(2) + (ly - cy).powi(2) > 1.0 {
+                return None;
+            }
+        }
+        Some((lx, ly))
+    }
+    // Camera space to world space.
+    fn to_world(&self, v: Vector) -> Vector {
+        v.x() * self.u + v.y() * self.v + v.z() * self.w
+    }
+    fn sample_square(&self, u: (f64, f64)) -> Vector {
+        Vector::new(u.0 - 0.5, u.1 - 0.5, 0.0)
+    }
+    // Also records the first hit attributes and the radiance by bounce.
+    fn ray_color<T: Hittable>(
+        &self,
+        r: Ray,
+        world: &T,
+        sampler: &mut dyn Sampler,
+        aovs: &mut Aovs,
+    ) -> Color {
+        let mut ray = r;
+        let mut primary = true;
+        let mut media = MediumStack::new();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut radiance = Color::zero();
+        let mut depth = 0;
+        let mut volume_depth = 0;
+        let mut pass_through = 0;
+        // Scatter events so far per lobe: diffuse, glossy, transmission.
+        let mut lobe_depth = [0; 3];
+        // Whether the last surface already received the sun as a light
+        // through the lobe the path continued with, so the sky is looked up
+        // without it.
+        let mut sun_sampled = false;
+
+        while depth < self.max_depth {
+            let mut rec = HitRecord::new();
+            let hit = world.hit(&ray, Interval::new(0.001, INF), &mut rec);
+
+            // Attenuation through the medium the segment travels in.
+            if let Some(medium) = media.current() {
+                let length = ray.direction().length();
+                let distance = if hit { rec.t * length } else { INF };
+                if medium.scatters() {
+                    let (absorption, scattering) = medium.coefficients(ray.wavelength());
+                    let sigma_t = absorption + scattering;
+                    // Free flight distance sampled from a randomly picked
+                    // channel, weighted by the average pdf over all channels.
+                    let (u1, u2) = sampler.get_2d();
+                    let channel = ((u1 * 3.0) as usize).min(2);
+                    let flight = -(1.0 - u2).ln() / sigma_t[channel];
+                    let travelled = flight.min(distance);
+                    let mut tr = Color::zero();
+                    for c in 0..3 {
+                        tr[c] = (-sigma_t[c] * travelled).exp();
+                    }
+                    if flight < distance {
+                        // Real scattering event inside the medium.
+                        if volume_depth >= self.max_volume_depth {
+                            break;
+                        }
+                        let pdf = Vector::dot(sigma_t, tr) / 3.0;
+                        throughput = throughput * scattering * tr / pdf;
+                        let direction =
+                            medium.sample_phase(ray.direction().unit(), sampler.get_2d());
+                        let mut next = Ray::new(ray.at(flight / length), direction);
+                        next.set_wavelength(ray.wavelength());
+                        next.set_time(ray.time());
+                        ray = next;
+                        sun_sampled = false;
+                        volume_depth += 1;
+                        depth += 1;
+                        continue;
+                    }
+                    if hit {
+                        throughput = throughput * tr / ((tr.x() + tr.y() + tr.z()) / 3.0);
+                    }
+                } else if hit {
+                    throughput = throughput * medium.transmittance(distance, ray.wavelength());
+                }
+            }
+
+            if !hit {
+                let background = if sun_sampled {
+                    self.background.value_without_sun(ray.direction())
                 } else {
-                    return Color::new(0.0, 0.0, 0.0); // Ray absorbed, return black
+                    self.background.value(ray.direction())
+                };
+                let background = self.to_working(background);
+                if primary {
+                    // Demodulating by the background keeps it unfiltered.
+                    aovs.albedo = background;
                 }
-            } else {
+                let contribution = throughput * self.path_color(background, &ray);
+                aovs.add_radiance(depth, contribution);
+                radiance += contribution;
+                break;
+            }
+
+            let Some(mat) = rec.material.clone() else {
                 // Debugging: If material is missing, log something
                 eprintln!("Warning: Object hit but no material found.");
+                let direction = rec.normal + sampler::unit_vector(sampler.get_2d());
+                let mut bounced = Ray::new(rec.p, direction);
+                bounced.set_wavelength(ray.wavelength());
+                bounced.set_time(ray.time());
+                ray = bounced;
+                throughput *= 0.7;
+                sun_sampled = false;
+                depth += 1;
+                continue;
+            };
+
+            let alpha = mat.alpha(&rec);
+            if alpha < 1.0 && sampler.get_1d() >= alpha {
+                // Transparent part of a cutout, the surface is not there.
+                if pass_through >= self.max_pass_through {
+                    break;
+                }
+                pass_through += 1;
+                let mut through = Ray::new(rec.p, ray.direction());
+                through.set_wavelength(ray.wavelength());
+                through.set_time(ray.time());
+                ray = through;
+                continue;
+            }
+
+            let id = Rc::as_ptr(&mat) as *const () as usize;
+            let medium = mat.medium();
+            let entering = rec.front_face;
+            if let Some(medium) = medium {
+                if media.is_false_hit(id, &medium, entering) {
+                    // The boundary is inside a higher priority medium,
+                    // continue as if it was not there.
+                    if pass_through >= self.max_pass_through {
+                        break;
+                    }
+                    pass_through += 1;
+                    media.cross(id, medium, entering);
+                    let mut through = Ray::new(rec.p, ray.direction());
+                    through.set_wavelength(ray.wavelength());
+                    through.set_time(ray.time());
+                    ray = through;
+                    continue;
+                }
+                rec.exterior_index = media.exterior_index(id, entering);
+            } else {
+                rec.exterior_index = media.refraction_index();
+            }
+
+            let contribution = throughput * self.path_color(mat.emitted(rec.u, rec.v, rec.p), &ray);
+            aovs.add_radiance(depth, contribution);
+            radiance += contribution;
+            if primary {
+                aovs.albedo = mat.albedo(&rec);
+                aovs.normal = rec.normal;
+                aovs.depth = (rec.p - r.origin()).length();
+                aovs.position = rec.p;
+                aovs.object_id = rec.object_id;
+                aovs.material_id = id;
+                primary = false;
+            }
+
+            // Direct light from the analytic lights and the sun, which
+            // camera rays and scattered rays never hit.
+            for light in &self.working_lights {
+                if let Some(contribution) =
+                    self.direct_light(light, world, &ray, &rec, &*mat, sampler)
+                {
+                    let contribution = throughput * contribution;
+                    aovs.add_radiance(depth + 1, contribution);
+                    radiance += contribution;
+                }
+            }
+            let mut sun_lit = false;
+            if let Some(sun) = &self.sun {
+                if let Some(contribution) =
+                    self.direct_light(sun, world, &ray, &rec, &*mat, sampler)
+                {
+                    let contribution = throughput * contribution;
+                    aovs.add_radiance(depth + 1, contribution);
+                    radiance += contribution;
+                    sun_lit = true;
+                }
+            }
+
+            let mut scattered = Ray::new(Point::zero(), Vector::zero());
+            let mut attenuation = Color::zero();
+            // Check if material scatters ray, otherwise it is absorbed
+            let Some(lobe) = mat.scatter(&ray, &rec, &mut attenuation, &mut scattered, sampler)
+            else {
+                break;
+            };
+            // Mirror reflections and transmissions are not part of eval, the
+            // sun is only found by hitting it.
+            sun_sampled = sun_lit && lobe.is_evaluated();
+            let (count, limit) = match lobe {
+                Lobe::Diffuse => (&mut lobe_depth[0], self.max_diffuse_depth),
+                Lobe::Glossy | Lobe::Specular => (&mut lobe_depth[1], self.max_glossy_depth),
+                Lobe::Transmission => (&mut lobe_depth[2], self.max_transmission_depth),
+            };
+            if *count >= limit {
+                break;
+            }
+            *count += 1;
+
+            if let Some(medium) = medium {
+                if Vector::dot(scattered.direction(), rec.normal) < 0.0 {
+                    media.cross(id, medium, entering);
+                }
             }
-            let direction = rec.normal + Vector::random_unit_vector();
-            return 0.7 * self.ray_color(Ray::new(rec.p, direction), depth - 1, world);
+            throughput = throughput * self.path_color(attenuation, &ray);
+            scattered.set_wavelength(ray.wavelength());
+            scattered.set_time(ray.time());
+            ray = scattered;
+            depth += 1;
+
+            // Russian roulette, survivors are reweighted to stay unbiased.
+            if depth >= self.russian_roulette_depth {
+                let survival = throughput
+                    .x()
+                    .max(throughput.y())
+                    .max(throughput.z())
+                    .min(1.0);
+                if survival <= 0.0 || sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        radiance
+    }
+    // Light arriving from `light` at the hit and scattered along the
+    // reversed ray, zero when something blocks it. None when the material
+    // does not reflect the light towards the ray at all, or when the
+    // blocker refracts the light, which only paths through it can carry.
+    fn direct_light<T: Hittable>(
+        &self,
+        light: &Light,
+        world: &T,
+        ray: &Ray,
+        rec: &HitRecord,
+        mat: &dyn Material,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Color> {
+        let (wi, distance, irradiance) = light.incident(rec.p)?;
+        let f = mat.eval(ray, rec, wi);
+        if f.near_zero() {
+            return None;
+        }
+        let mut shadow = Ray::new(rec.p, wi);
+        shadow.set_time(ray.time());
+        let mut near = 0.001;
+        let mut pass_through = 0;
+        loop {
+            let mut blocker = HitRecord::new();
+            if !world.hit(&shadow, Interval::new(near, distance), &mut blocker) {
+                break;
+            }
+            let Some(blocker_mat) = blocker.material.clone() else {
+                return Some(Color::zero());
+            };
+            let alpha = blocker_mat.alpha(&blocker);
+            if alpha < 1.0 && sampler.get_1d() >= alpha {
+                // Transparent part of a cutout, as for the path itself.
+                if pass_through >= self.max_pass_through {
+                    return Some(Color::zero());
+                }
+                pass_through += 1;
+                near = blocker.t + 0.001;
+                continue;
+            }
+            return (!blocker_mat.transmits(&blocker)).then(Color::zero);
+        }
+        Some(self.path_color(f, ray) * self.path_color(irradiance, ray))
+    }
+    // Colors in linear sRGB, such as the sky's and spectral samples', in the
+    // working space and back.
+    fn to_working(&self, c: Color) -> Color {
+        if self.working_space == ColorSpace::Srgb {
+            c
+        } else {
+            colorspace::mul(&self.srgb_to_working, c)
+        }
+    }
+    fn to_srgb(&self, c: Color) -> Color {
+        if self.working_space == ColorSpace::Srgb {
+            c
+        } else {
+            colorspace::mul(&self.working_to_srgb, c)
         }
-        let unit_direction = r.direction().unit();
-        let a = 0.5 * (unit_direction.y() + 1.0);
-        (1.0 - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0)
+    }
+    // On spectral paths RGB quantities are upsampled to the path wavelength
+    // and carried as a grey color.
+    fn path_color(&self, c: Color, r: &Ray) -> Color {
+        match r.wavelength() {
+            Some(lambda) => {
+                let s = spectrum::rgb_to_spectrum(self.to_srgb(c), lambda);
+                Color::new(s, s, s)
+            }
+            None => c,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{HittableList, Sphere};
+    use crate::light::Power;
+    use crate::material::{Cutout, Lambertian};
+    use crate::sampler::IndependentSampler;
+    use crate::texture;
+
+    #[test]
+    fn f_number_opens_the_aperture() {
+        let mut camera = Camera::new(1.0, 10, 1, 40.0);
+        camera.f_number = Some(1.4);
+        camera.initialize();
+        let mut sampler = IndependentSampler;
+        let origins: Vec<Point> = (0..16)
+            .map(|_| {
+                let (ray, _) = camera
+                    .get_ray(5, 5, Vector::zero(), None, &mut sampler)
+                    .unwrap();
+                ray.origin()
+            })
+            .collect();
+        assert!(origins.iter().any(|&o| !(o - camera.center).near_zero()));
+        // Without either setting the camera stays a pinhole.
+        let mut pinhole = Camera::new(1.0, 10, 1, 40.0);
+        pinhole.initialize();
+        let (ray, _) = pinhole
+            .get_ray(5, 5, Vector::zero(), None, &mut sampler)
+            .unwrap();
+        assert!(ray.origin().near_zero());
+    }
+
+    #[test]
+    fn lights_shine_through_transparent_cutouts() {
+        let ground: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let light = Light::point(
+            Point::new(0.0, 4.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            Power::Watts(100.0),
+        );
+        let camera = Camera::new(1.0, 10, 1, 40.0);
+        let mut sampler = IndependentSampler;
+        let mut lit = |alpha: f64| {
+            let leaf = Cutout::new(ground.clone(), texture::constant(alpha));
+            let mut world = HittableList::new();
+            world.add(Sphere::new(
+                Point::new(0.0, -1000.0, 0.0),
+                1000.0,
+                Some(ground.clone()),
+            ));
+            world.add(Sphere::new(
+                Point::new(0.0, 2.0, 0.0),
+                0.5,
+                Some(Rc::new(leaf)),
+            ));
+            let ray = Ray::new(Point::new(0.0, 1.0, 1.0), Vector::new(0.0, -1.0, -1.0));
+            let mut rec = HitRecord::new();
+            assert!(world.hit(&ray, Interval::new(0.001, INF), &mut rec));
+            camera
+                .direct_light(&light, &world, &ray, &rec, &*ground, &mut sampler)
+                .unwrap()
+        };
+        assert!(lit(0.0).x() > 0.0);
+        assert!(lit(1.0).near_zero());
+    }
+
+    #[test]
+    fn anamorphic_lenses_widen_the_field() {
+        let mut sampler = IndependentSampler;
+        // Horizontal slope of the ray through the right edge of the middle
+        // row, and the image height.
+        let mut edge = |squeeze: f64| {
+            let mut camera = Camera::new(1.5, 300, 1, 40.0);
+            camera.anamorphic_squeeze = squeeze;
+            camera.initialize();
+            let offset = Vector::new(0.5, 0.0, 0.0);
+            let j = camera.image_height / 2;
+            let (ray, _) = camera.get_ray(299, j, offset, None, &mut sampler).unwrap();
+            let d = ray.direction();
+            (d.x() / -d.z(), camera.image_height)
+        };
+        let (spherical, spherical_height) = edge(1.0);
+        let (anamorphic, anamorphic_height) = edge(2.0);
+        assert_eq!((spherical_height, anamorphic_height), (200, 100));
+        assert!((anamorphic / spherical - 2.0).abs() < 1e-9);
     }
 }
@@ -1,6 +1,6 @@
 use std::io::{self, Write};
 
-use crate::{interval::Interval, rtweeknd::INTENSITY_INTERVAL, vector::Vector};
+use crate::{rtweeknd::INTENSITY_INTERVAL, vector::Vector};
 
 pub type Color = Vector;
 
@@ -9,9 +9,9 @@ pub fn write_color<W: Write>(out: &mut W, pixel_color: Color) -> io::Result<()>
     let mut g = pixel_color.y();
     let mut b = pixel_color.z();
 
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+    r = linear_to_srgb(r);
+    g = linear_to_srgb(g);
+    b = linear_to_srgb(b);
 
     // Translate the [0,1] component values to the byte range [0,255].
     let rbyte = (256.0 * INTENSITY_INTERVAL.clamp(r)) as i32;
@@ -21,6 +21,24 @@ pub fn write_color<W: Write>(out: &mut W, pixel_color: Color) -> io::Result<()>
     writeln!(out, "{} {} {}", rbyte, gbyte, bbyte)
 }
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    f64::sqrt(linear_component)
+// The sRGB transfer function (OETF), linear to encoded.
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Decodes an sRGB encoded component to linear.
+pub fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
 }
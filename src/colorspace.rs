@@ -0,0 +1,269 @@
+use crate::color::{self, Color};
+use crate::vector::Vector;
+
+pub type Matrix = [[f64; 3]; 3];
+
+// RGB color spaces, used as the working space the scene's colors are given
+// and rendered in, and as the display space of the written image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    // Rec. 709 primaries and D65 white.
+    #[default]
+    Srgb,
+    // Wide gamut of UHD television, D65 white.
+    Rec2020,
+    // ACES AP1 primaries and the ACES white, near D60.
+    AcesCg,
+    // DCI-P3 primaries with D65 white, as on wide gamut monitors.
+    DisplayP3,
+}
+
+impl ColorSpace {
+    // xy chromaticities of the red, green and blue primaries and white.
+    fn chromaticities(self) -> [(f64, f64); 4] {
+        match self {
+            ColorSpace::Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            ColorSpace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+            ColorSpace::AcesCg => [
+                (0.713, 0.293),
+                (0.165, 0.830),
+                (0.128, 0.044),
+                (0.32168, 0.33767),
+            ],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+        }
+    }
+
+    pub fn white(self) -> (f64, f64) {
+        self.chromaticities()[3]
+    }
+
+    // Linear RGB to CIE XYZ, scaled so that white has Y = 1.
+    pub fn rgb_to_xyz(self) -> Matrix {
+        let [r, g, b, w] = self.chromaticities();
+        let primaries = transpose(&[xy_to_xyz(r), xy_to_xyz(g), xy_to_xyz(b)]);
+        // Primary intensities that add up to white.
+        let s = mul(&invert(&primaries), xy_to_xyz(w));
+        let mut m = primaries;
+        for row in m.iter_mut() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value *= s[c];
+            }
+        }
+        m
+    }
+
+    pub fn xyz_to_rgb(self) -> Matrix {
+        invert(&self.rgb_to_xyz())
+    }
+
+    // Matrix from linear RGB in this space to `to`, adapting white with
+    // Bradford when the white points differ.
+    pub fn conversion(self, to: ColorSpace) -> Matrix {
+        let adapt = bradford(self.white(), to.white());
+        product(&to.xyz_to_rgb(), &product(&adapt, &self.rgb_to_xyz()))
+    }
+
+    pub fn convert(self, c: Color, to: ColorSpace) -> Color {
+        if self == to {
+            return c;
+        }
+        mul(&self.conversion(to), c)
+    }
+
+    // Color given in linear sRGB, e.g. picked in an image editor, in this
+    // space.
+    pub fn convert_srgb(self, c: Color) -> Color {
+        ColorSpace::Srgb.convert(c, self)
+    }
+
+    // Relative luminance of a linear color in this space. sRGB uses the
+    // published Rec. 709 weights.
+    pub fn luminance(self, c: Color) -> f64 {
+        if self == ColorSpace::Srgb {
+            return color::luminance(c);
+        }
+        let m = self.rgb_to_xyz();
+        m[1][0] * c.x() + m[1][1] * c.y() + m[1][2] * c.z()
+    }
+
+    // Transfer function of the space as a display encoding: the sRGB curve
+    // for sRGB and Display P3, the BT.1886 2.4 power for Rec. 2020, and
+    // none for ACEScg.
+    pub fn encode(self, linear: f64) -> f64 {
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => color::linear_to_srgb(linear),
+            ColorSpace::Rec2020 => linear.max(0.0).powf(1.0 / 2.4),
+            ColorSpace::AcesCg => linear,
+        }
+    }
+
+    // White balance matrix for linear RGB in this space: light of color
+    // temperature `kelvin` is rendered as the space's white.
+    pub fn white_balance(self, kelvin: f64) -> Matrix {
+        let adapt = bradford(temperature_to_xy(kelvin), self.white());
+        product(&self.xyz_to_rgb(), &product(&adapt, &self.rgb_to_xyz()))
+    }
+}
+
+const D65: (f64, f64) = (0.3127, 0.3290);
+
+// Chromaticity of the light of a color temperature: the Planckian locus
+// below 4000K (Kim et al. 2002), CIE daylight above, so that 6504K is D65.
+pub fn temperature_to_xy(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    if t < 4000.0 {
+        let x = -0.2661239e9 / (t * t * t) - 0.2343589e6 / (t * t) + 0.8776956e3 / t + 0.179910;
+        let y = if t < 2222.0 {
+            -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+        } else {
+            -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+        };
+        (x, y)
+    } else {
+        let x = if t <= 7000.0 {
+            0.244063 + 0.09911e3 / t + 2.9678e6 / (t * t) - 4.6070e9 / (t * t * t)
+        } else {
+            0.237040 + 0.24748e3 / t + 1.9018e6 / (t * t) - 2.0064e9 / (t * t * t)
+        };
+        (x, -3.0 * x * x + 2.870 * x - 0.275)
+    }
+}
+
+// Chromatic adaptation of XYZ colors from one white to another in the
+// Bradford cone response space.
+fn bradford(from: (f64, f64), to: (f64, f64)) -> Matrix {
+    const BRADFORD: Matrix = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let source = mul(&BRADFORD, xy_to_xyz(from));
+    let target = mul(&BRADFORD, xy_to_xyz(to));
+    let mut scale = [[0.0; 3]; 3];
+    for i in 0..3 {
+        scale[i][i] = target[i] / source[i];
+    }
+    product(&invert(&BRADFORD), &product(&scale, &BRADFORD))
+}
+
+fn xy_to_xyz((x, y): (f64, f64)) -> Vector {
+    Vector::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+pub fn mul(m: &Matrix, c: Color) -> Color {
+    Color::new(
+        m[0][0] * c.x() + m[0][1] * c.y() + m[0][2] * c.z(),
+        m[1][0] * c.x() + m[1][1] * c.y() + m[1][2] * c.z(),
+        m[2][0] * c.x() + m[2][1] * c.y() + m[2][2] * c.z(),
+    )
+}
+
+pub fn product(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn transpose(m: &[Vector; 3]) -> Matrix {
+    let mut t = [[0.0; 3]; 3];
+    for (i, row) in t.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = m[j][i];
+        }
+    }
+    t
+}
+
+fn invert(m: &Matrix) -> Matrix {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    [
+        [
+            cofactor(1, 2, 1, 2) / det,
+            -cofactor(0, 2, 1, 2) / det,
+            cofactor(0, 1, 1, 2) / det,
+        ],
+        [
+            -cofactor(1, 2, 0, 2) / det,
+            cofactor(0, 2, 0, 2) / det,
+            -cofactor(0, 1, 0, 2) / det,
+        ],
+        [
+            cofactor(1, 2, 0, 1) / det,
+            -cofactor(0, 2, 0, 1) / det,
+            cofactor(0, 1, 0, 1) / det,
+        ],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 4] = [
+        ColorSpace::Srgb,
+        ColorSpace::Rec2020,
+        ColorSpace::AcesCg,
+        ColorSpace::DisplayP3,
+    ];
+
+    fn assert_close(a: &Matrix, b: &Matrix, tolerance: f64) {
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((a[i][j] - b[i][j]).abs() < tolerance, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        for from in SPACES {
+            assert_close(&from.conversion(from), &identity, 1e-12);
+            for to in SPACES {
+                let there_and_back = product(&to.conversion(from), &from.conversion(to));
+                assert_close(&there_and_back, &identity, 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn white_stays_white() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for from in SPACES {
+            assert!((from.luminance(white) - 1.0).abs() < 1e-3);
+            for to in SPACES {
+                let c = from.convert(white, to);
+                assert!(
+                    (c - white).length() < 1e-3,
+                    "{:?} -> {:?}: {:?}",
+                    from,
+                    to,
+                    c
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_to_rec2020_matches_bt2087() {
+        // ITU-R BT.2087, rounded to four places.
+        let published = [
+            [0.6274, 0.3293, 0.0433],
+            [0.0691, 0.9195, 0.0114],
+            [0.0164, 0.0880, 0.8956],
+        ];
+        assert_close(
+            &ColorSpace::Srgb.conversion(ColorSpace::Rec2020),
+            &published,
+            1e-3,
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::color::{self, Color};
+use crate::framebuffer::Framebuffer;
+use crate::image::Image;
+use crate::vector::Vector;
+
+// Lowest albedo divided out of the image, keeps dark surfaces from blowing
+// up the demodulated irradiance.
+const MIN_ALBEDO: f64 = 0.01;
+
+// B3 spline kernel of the wavelet transform.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Edge-avoiding a-trous wavelet filter after Dammertz et al., "Edge-Avoiding
+// A-Trous Wavelet Transform for fast Global Illumination Filtering" (2010),
+// with the luminance variance guided color weights of Schied et al.,
+// "Spatiotemporal Variance-Guided Filtering" (2017).
+//
+// The image is divided by the first hit albedo so that texture detail is
+// not blurred, filtered with growing strides, and multiplied back.
+#[derive(Clone, Copy, Debug)]
+pub struct Denoiser {
+    pub iterations: u32,
+    // Edge stopping strengths. Color is in units of the per pixel standard
+    // error, normal is the exponent on the cosine between normals, depth
+    // is relative to the distance from the camera.
+    pub sigma_color: f64,
+    pub sigma_normal: f64,
+    pub sigma_depth: f64,
+    pub sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_normal: 64.0,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+impl Denoiser {
+    pub fn denoise(&self, framebuffer: &Framebuffer) -> Image {
+        let width = framebuffer.width();
+        let height = framebuffer.height();
+        let n = width * height;
+
+        let mut albedo = Vec::with_capacity(n);
+        let mut normal = Vec::with_capacity(n);
+        let mut depth = Vec::with_capacity(n);
+        let mut irradiance = Vec::with_capacity(n);
+        let mut variance = Vec::with_capacity(n);
+        for y in 0..height {
+            for x in 0..width {
+                let a = framebuffer.albedo(x, y);
+                let a = Color::new(
+                    a.x().max(MIN_ALBEDO),
+                    a.y().max(MIN_ALBEDO),
+                    a.z().max(MIN_ALBEDO),
+                );
+                let samples = framebuffer.samples(x, y).max(1) as f64;
+                let l = color::luminance(a);
+                albedo.push(a);
+                normal.push(framebuffer.normal(x, y));
+                depth.push(framebuffer.depth(x, y));
+                irradiance.push(
+                    framebuffer.pixel(x, y) * Color::new(1.0 / a.x(), 1.0 / a.y(), 1.0 / a.z()),
+                );
+                variance.push(framebuffer.variance(x, y) / samples / (l * l));
+            }
+        }
+
+        let mut filtered = vec![Color::zero(); n];
+        let mut filtered_variance = vec![0.0; n];
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            let blurred = blur_variance(&variance, width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    let p = y * width + x;
+                    let lum_p = color::luminance(irradiance[p]);
+                    let std_error = blurred[p].max(0.0).sqrt();
+                    let mut sum = Color::zero();
+                    let mut sum_variance = 0.0;
+                    let mut total = 0.0;
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        let qy = y as i64 + (ky as i64 - 2) * step;
+                        if qy < 0 || qy >= height as i64 {
+                            continue;
+                        }
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            let qx = x as i64 + (kx as i64 - 2) * step;
+                            if qx < 0 || qx >= width as i64 {
+                                continue;
+                            }
+                            let q = qy as usize * width + qx as usize;
+
+                            let lum_q = color::luminance(irradiance[q]);
+                            let w_color =
+                                (lum_p - lum_q).abs() / (self.sigma_color * std_error + 1e-4);
+                            let w_albedo = (albedo[p] - albedo[q]).length() / self.sigma_albedo;
+                            let w_depth = depth_distance(depth[p], depth[q]) / self.sigma_depth;
+                            let w_normal = if normal[p].near_zero() && normal[q].near_zero() {
+                                1.0
+                            } else {
+                                Vector::dot(normal[p], normal[q])
+                                    .max(0.0)
+                                    .powf(self.sigma_normal)
+                            };
+                            let w = hx * hy * w_normal * (-w_color - w_albedo - w_depth).exp();
+                            sum += w * irradiance[q];
+                            sum_variance += w * w * variance[q];
+                            total += w;
+                        }
+                    }
+                    filtered[p] = sum / total;
+                    filtered_variance[p] = sum_variance / (total * total);
+                }
+            }
+            std::mem::swap(&mut irradiance, &mut filtered);
+            std::mem::swap(&mut variance, &mut filtered_variance);
+        }
+
+        let data = irradiance
+            .iter()
+            .zip(albedo.iter())
+            .map(|(&e, &a)| e * a)
+            .collect();
+        Image::new(width, height, data)
+    }
+}
+
+// The per pixel variance estimate is itself noisy at low sample counts, so
+// the color weights use a 3x3 Gaussian blur of it.
+fn blur_variance(variance: &[f64], width: usize, height: usize) -> Vec<f64> {
+    const WEIGHTS: [f64; 3] = [0.25, 0.5, 0.25];
+    let mut blurred = vec![0.0; variance.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            let mut total = 0.0;
+            for (dy, wy) in WEIGHTS.iter().enumerate() {
+                for (dx, wx) in WEIGHTS.iter().enumerate() {
+                    let (qx, qy) = ((x + dx) as i64 - 1, (y + dy) as i64 - 1);
+                    if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                        continue;
+                    }
+                    sum += wx * wy * variance[qy as usize * width + qx as usize];
+                    total += wx * wy;
+                }
+            }
+            blurred[y * width + x] = sum / total;
+        }
+    }
+    blurred
+}
+
+fn depth_distance(a: f64, b: f64) -> f64 {
+    if a.is_infinite() && b.is_infinite() {
+        0.0
+    } else if a.is_infinite() || b.is_infinite() {
+        f64::INFINITY
+    } else {
+        (a - b).abs() / a.min(b).max(1e-3)
+    }
+}
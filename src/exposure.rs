@@ -0,0 +1,86 @@
+use crate::colorspace::ColorSpace;
+use crate::image::Image;
+
+// Film exposure of the photographic settings relative to f/1, one second
+// and ISO 100 (EV 0), which leaves scene radiance as it is.
+pub fn exposure(f_number: f64, shutter: f64, iso: f64) -> f64 {
+    shutter / (f_number * f_number) * iso / 100.0
+}
+
+// Range of the luminance histogram in stops, and its resolution.
+const MIN_LOG_LUMINANCE: f64 = -16.0;
+const MAX_LOG_LUMINANCE: f64 = 16.0;
+const BINS: usize = 128;
+
+// Metering like a camera's: the image is scaled so that the average log
+// luminance of a window of its histogram becomes middle grey. Leaving out
+// the darkest and brightest pixels keeps shadows, the sun or small
+// highlights from swinging the result.
+#[derive(Clone, Copy, Debug)]
+pub struct AutoExposure {
+    // Window of the histogram, as fractions of the pixels sorted by
+    // luminance.
+    pub low_percentile: f64,
+    pub high_percentile: f64,
+    // Luminance the window average is mapped to.
+    pub key: f64,
+    // Limits of the adjustment, in stops.
+    pub min_stops: f64,
+    pub max_stops: f64,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        AutoExposure {
+            low_percentile: 0.5,
+            high_percentile: 0.95,
+            key: 0.18,
+            min_stops: -10.0,
+            max_stops: 10.0,
+        }
+    }
+}
+
+impl AutoExposure {
+    // Factor the image, with colors in `space`, should be multiplied by.
+    pub fn exposure(&self, image: &Image, space: ColorSpace) -> f64 {
+        let scale = BINS as f64 / (MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE);
+        let mut histogram = [0usize; BINS];
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let l = space.luminance(image.pixel(x, y));
+                // Black pixels fall into the lowest bin.
+                let bin = if l > 0.0 {
+                    ((l.log2() - MIN_LOG_LUMINANCE) * scale).clamp(0.0, (BINS - 1) as f64) as usize
+                } else {
+                    0
+                };
+                histogram[bin] += 1;
+            }
+        }
+
+        let total = (image.width() * image.height()) as f64;
+        let low = self.low_percentile.clamp(0.0, 1.0) * total;
+        let high = self.high_percentile.clamp(0.0, 1.0).max(low / total) * total;
+        let mut below = 0.0;
+        let mut sum = 0.0;
+        let mut count = 0.0;
+        for (bin, &n) in histogram.iter().enumerate() {
+            // Part of the bin inside the window.
+            let n = n as f64;
+            let inside = (below + n).min(high) - below.max(low);
+            below += n;
+            if inside <= 0.0 {
+                continue;
+            }
+            let log_luminance = MIN_LOG_LUMINANCE + (bin as f64 + 0.5) / scale;
+            sum += inside * log_luminance;
+            count += inside;
+        }
+        if count <= 0.0 {
+            return 1.0;
+        }
+        let stops = (self.key.log2() - sum / count).clamp(self.min_stops, self.max_stops);
+        stops.exp2()
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Minimal OpenEXR writer: a single part scanline image with uncompressed
+// 32 bit float channels. Channel names may carry a layer prefix, e.g.
+// "normal.X", which compositors show as separate layers.
+pub struct ExrChannel {
+    pub name: String,
+    // width * height values, top row first.
+    pub data: Vec<f32>,
+}
+
+impl ExrChannel {
+    pub fn new(name: impl Into<String>, data: Vec<f32>) -> Self {
+        ExrChannel {
+            name: name.into(),
+            data,
+        }
+    }
+}
+
+const MAGIC: u32 = 20000630;
+const FLOAT: i32 = 2;
+
+pub fn write_exr<W: Write>(
+    out: &mut W,
+    width: usize,
+    height: usize,
+    channels: &[ExrChannel],
+) -> io::Result<()> {
+    // Readers expect the channels sorted by name.
+    let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    for channel in &channels {
+        if channel.data.len() != width * height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("channel {} does not match the image size", channel.name),
+            ));
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    // Version 2, single part scanline file.
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for channel in &channels {
+        chlist.extend_from_slice(channel.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&FLOAT.to_le_bytes());
+        // pLinear and reserved bytes.
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        // x and y sampling.
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    attribute(&mut header, "channels", "chlist", &chlist);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y.
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    let mut center = Vec::new();
+    center.extend_from_slice(&0f32.to_le_bytes());
+    center.extend_from_slice(&0f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &center);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+    out.write_all(&header)?;
+
+    // Offset table, one uncompressed scanline per chunk.
+    let line_size = channels.len() * width * 4;
+    let chunk_size = 8 + line_size;
+    let table_end = header.len() + height * 8;
+    for y in 0..height {
+        let offset = (table_end + y * chunk_size) as u64;
+        out.write_all(&offset.to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        line.clear();
+        for channel in &channels {
+            for value in &channel.data[y * width..(y + 1) * width] {
+                line.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+pub fn save_exr<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    channels: &[ExrChannel],
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_exr(&mut out, width, height, channels)?;
+    out.flush()
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
@@ -0,0 +1,111 @@
+use crate::rtweeknd::PI;
+
+// Pixel reconstruction filter. Samples are splatted onto every pixel whose
+// center is within `radius` (in pixels) along both axes, weighted by the
+// filter. All filters are separable.
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    // Plain average of the samples over a square.
+    Box { radius: f64 },
+    // Linear falloff, slightly softer than the box.
+    Tent { radius: f64 },
+    // Gaussian shifted down so it reaches zero at the radius.
+    Gaussian { radius: f64, sigma: f64 },
+    // Mitchell and Netravali, "Reconstruction Filters in Computer Graphics"
+    // (1988). The negative lobes sharpen, B = C = 1/3 is the recommended
+    // balance between blurring and ringing.
+    Mitchell { radius: f64, b: f64, c: f64 },
+    // Blackman-Harris window, close to a Gaussian but with a smaller tail.
+    BlackmanHarris { radius: f64 },
+}
+
+impl Default for Filter {
+    // A box over a single pixel, each sample only counts for its own pixel.
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn tent() -> Self {
+        Filter::Tent { radius: 1.0 }
+    }
+
+    pub fn gaussian() -> Self {
+        Filter::Gaussian {
+            radius: 1.5,
+            sigma: 0.5,
+        }
+    }
+
+    pub fn mitchell() -> Self {
+        Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub fn blackman_harris() -> Self {
+        Filter::BlackmanHarris { radius: 2.0 }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::BlackmanHarris { radius } => radius,
+        }
+    }
+
+    // Weight of a sample at offset (x, y) from the pixel center.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match *self {
+            Filter::Box { radius } => {
+                if x <= radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Tent { radius } => (radius - x).max(0.0),
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                if x >= radius {
+                    return 0.0;
+                }
+                // The cubic is defined over [-2, 2].
+                let x = 2.0 * x / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                }
+            }
+            Filter::BlackmanHarris { radius } => {
+                if x >= radius {
+                    return 0.0;
+                }
+                let t = 2.0 * PI * (x + radius) / (2.0 * radius);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::aov::Aovs;
+use crate::color::Color;
+use crate::colorspace::ColorSpace;
+use crate::filter::Filter;
+use crate::image::Image;
+use crate::vector::{Point, Vector};
+
+// Film that accumulates radiance samples across rendering passes. Samples
+// are splatted onto the neighbouring pixels weighted by the reconstruction
+// filter. Running luminance statistics (Welford) of the samples taken in
+// each pixel estimate the remaining noise.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    // Space of the samples, for their luminance.
+    space: ColorSpace,
+    sum: Vec<Color>,
+    weight: Vec<f64>,
+    samples: Vec<u32>,
+    mean: Vec<f64>,
+    m2: Vec<f64>,
+    // Sums of the first hit attributes of the samples taken in each pixel.
+    albedo: Vec<Color>,
+    normal: Vec<Vector>,
+    depth: Vec<f64>,
+    position: Vec<Point>,
+    // Identities seen by the first sample of each pixel.
+    object_id: Vec<u32>,
+    material_id: Vec<usize>,
+    // Radiance split by bounces, filtered like the beauty image.
+    emission: Vec<Color>,
+    direct: Vec<Color>,
+    indirect: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        let n = width * height;
+        Framebuffer {
+            width,
+            height,
+            filter: Filter::default(),
+            space: ColorSpace::default(),
+            sum: vec![Color::zero(); n],
+            weight: vec![0.0; n],
+            samples: vec![0; n],
+            mean: vec![0.0; n],
+            m2: vec![0.0; n],
+            albedo: vec![Color::zero(); n],
+            normal: vec![Vector::zero(); n],
+            depth: vec![0.0; n],
+            position: vec![Point::zero(); n],
+            object_id: vec![0; n],
+            material_id: vec![0; n],
+            emission: vec![Color::zero(); n],
+            direct: vec![Color::zero(); n],
+            indirect: vec![Color::zero(); n],
+        }
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_space(mut self, space: ColorSpace) -> Self {
+        self.space = space;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Adds a sample taken in pixel (x, y) at `offset` from its center.
+    pub fn add_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        offset: (f64, f64),
+        sample: Color,
+        aovs: &Aovs,
+    ) {
+        let radius = self.filter.radius();
+        let fx = x as f64 + offset.0;
+        let fy = y as f64 + offset.1;
+        // Pixels whose center is at an offset in [-radius, radius).
+        let x0 = ((fx - radius).floor() as i64 + 1).max(0);
+        let y0 = ((fy - radius).floor() as i64 + 1).max(0);
+        let x1 = ((fx + radius).floor() as i64).min(self.width as i64 - 1);
+        let y1 = ((fy + radius).floor() as i64).min(self.height as i64 - 1);
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let weight = self.filter.evaluate(fx - px as f64, fy - py as f64);
+                if weight != 0.0 {
+                    let j = py as usize * self.width + px as usize;
+                    self.sum[j] += weight * sample;
+                    self.weight[j] += weight;
+                    self.emission[j] += weight * aovs.emission;
+                    self.direct[j] += weight * aovs.direct;
+                    self.indirect[j] += weight * aovs.indirect;
+                }
+            }
+        }
+
+        let i = y * self.width + x;
+        if self.samples[i] == 0 {
+            self.object_id[i] = aovs.object_id;
+            self.material_id[i] = aovs.material_id;
+        }
+        self.samples[i] += 1;
+        self.albedo[i] += aovs.albedo;
+        self.normal[i] += aovs.normal;
+        self.depth[i] += aovs.depth;
+        self.position[i] += aovs.position;
+
+        let l = self.space.luminance(sample);
+        let delta = l - self.mean[i];
+        self.mean[i] += delta / self.samples[i] as f64;
+        self.m2[i] += delta * (l - self.mean[i]);
+    }
+
+    pub fn albedo(&self, x: usize, y: usize) -> Color {
+        let i = y * self.width + x;
+        self.albedo[i] / self.samples[i].max(1) as f64
+    }
+
+    pub fn normal(&self, x: usize, y: usize) -> Vector {
+        let n = self.normal[y * self.width + x];
+        if n.near_zero() {
+            Vector::zero()
+        } else {
+            n.unit()
+        }
+    }
+
+    // Average distance to the first hit, infinite if any sample escaped.
+    pub fn depth(&self, x: usize, y: usize) -> f64 {
+        let i = y * self.width + x;
+        self.depth[i] / self.samples[i].max(1) as f64
+    }
+
+    pub fn position(&self, x: usize, y: usize) -> Point {
+        let i = y * self.width + x;
+        self.position[i] / self.samples[i].max(1) as f64
+    }
+
+    pub fn object_id(&self, x: usize, y: usize) -> u32 {
+        self.object_id[y * self.width + x]
+    }
+
+    pub fn material_id(&self, x: usize, y: usize) -> usize {
+        self.material_id[y * self.width + x]
+    }
+
+    pub fn emission(&self, x: usize, y: usize) -> Color {
+        self.filtered(&self.emission, x, y)
+    }
+
+    pub fn direct(&self, x: usize, y: usize) -> Color {
+        self.filtered(&self.direct, x, y)
+    }
+
+    pub fn indirect(&self, x: usize, y: usize) -> Color {
+        self.filtered(&self.indirect, x, y)
+    }
+
+    fn filtered(&self, buffer: &[Color], x: usize, y: usize) -> Color {
+        let i = y * self.width + x;
+        if self.weight[i] <= 0.0 {
+            Color::zero()
+        } else {
+            buffer[i] / self.weight[i]
+        }
+    }
+
+    pub fn samples(&self, x: usize, y: usize) -> u32 {
+        self.samples[y * self.width + x]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.filtered(&self.sum, x, y)
+    }
+
+    // Unbiased variance of the pixel luminance samples.
+    pub fn variance(&self, x: usize, y: usize) -> f64 {
+        let i = y * self.width + x;
+        if self.samples[i] < 2 {
+            0.0
+        } else {
+            self.m2[i] / (self.samples[i] - 1) as f64
+        }
+    }
+
+    // Standard error of the pixel mean relative to its brightness. The
+    // denominator is padded so that near black pixels do not dominate.
+    pub fn relative_error(&self, x: usize, y: usize) -> f64 {
+        let n = self.samples(x, y);
+        if n < 2 {
+            return f64::INFINITY;
+        }
+        let std_error = (self.variance(x, y) / n as f64).sqrt();
+        std_error / (self.mean[y * self.width + x].abs() + 1e-2)
+    }
+
+    pub fn average_samples(&self) -> f64 {
+        let total: u64 = self.samples.iter().map(|&n| n as u64).sum();
+        total as f64 / self.samples.len() as f64
+    }
+
+    // Mean relative error over the whole image.
+    pub fn noise(&self) -> f64 {
+        let mut total = 0.0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                total += self.relative_error(x, y);
+            }
+        }
+        total / (self.width * self.height) as f64
+    }
+
+    pub fn to_image(&self) -> Image {
+        let mut data = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data.push(self.pixel(x, y));
+            }
+        }
+        Image::new(self.width, self.height, data)
+    }
+
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.to_image().write_ppm(out)
+    }
+
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.to_image().save_ppm(path)
+    }
+}
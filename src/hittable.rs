@@ -2,7 +2,9 @@ use std::rc::Rc;
 
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::rtweeknd::PI;
 use crate::vector::{Point, Vector};
 
 #[derive(Clone)]
@@ -11,7 +13,24 @@ pub struct HitRecord {
     pub normal: Vector,
     pub material: Option<Rc<dyn Material>>,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    // Surface derivatives dp/du and dp/dv, not normalized.
+    pub tangent: Vector,
+    pub bitangent: Vector,
     pub front_face: bool,
+    // Index of refraction on the outward normal side, filled in by the
+    // camera from the media the path is currently inside of.
+    pub exterior_index: f64,
+    // 1-based index of the object in the scene list. With nested lists it
+    // is the index of the outermost entry.
+    pub object_id: u32,
+}
+
+impl Default for HitRecord {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HitRecord {
@@ -21,7 +40,13 @@ impl HitRecord {
             normal: Vector::zero(),
             material: None,
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            tangent: Vector::zero(),
+            bitangent: Vector::zero(),
             front_face: false,
+            exterior_index: 1.0,
+            object_id: 0,
         }
     }
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vector) {
@@ -33,6 +58,14 @@ impl HitRecord {
             self.normal = -outward_normal;
         }
     }
+
+    pub fn outward_normal(&self) -> Vector {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
 }
 
 pub trait Hittable {
@@ -42,6 +75,8 @@ pub trait Hittable {
 pub struct Sphere {
     center: Point,
     radius: f64,
+    // Distance the center moves per second, blurred over the shutter time.
+    velocity: Vector,
     pub material: Option<Rc<dyn Material>>,
 }
 
@@ -51,14 +86,29 @@ impl Sphere {
         Sphere {
             center,
             radius,
+            velocity: Vector::zero(),
             material,
         }
     }
+
+    pub fn with_velocity(mut self, velocity: Vector) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    // p is a point on the unit sphere centered at the origin. u runs around
+    // the y axis starting at -x, v from the bottom (-y) to the top.
+    fn get_sphere_uv(p: Point) -> (f64, f64) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let oc = self.center - r.origin();
+        let center = self.center + r.time() * self.velocity;
+        let oc = center - r.origin();
         let a = r.direction().length_squared();
         let h = Vector::dot(r.direction(), oc);
         let c = oc.length_squared() - self.radius * self.radius;
@@ -69,22 +119,109 @@ impl Hittable for Sphere {
         }
 
         let discriminant_sqrt = f64::sqrt(discriminant);
-        let root = (h - discriminant_sqrt) / a;
+        let mut root = (h - discriminant_sqrt) / a;
         if !ray_t.surrounds(root) {
-            let root = (h + discriminant_sqrt) / a;
+            root = (h + discriminant_sqrt) / a;
             if !ray_t.surrounds(root) {
                 return false;
             }
         }
         rec.t = root;
         rec.p = r.at(rec.t);
-        let outward_normal = (rec.p - self.center) / self.radius;
+        let outward_normal = (rec.p - center) / self.radius;
+        rec.set_face_normal(r, outward_normal);
+        (rec.u, rec.v) = Self::get_sphere_uv(outward_normal);
+        let phi = 2.0 * PI * rec.u;
+        let theta = PI * rec.v;
+        rec.tangent = 2.0 * PI * self.radius * Vector::new(phi.sin(), 0.0, phi.cos()) * theta.sin();
+        rec.bitangent = PI
+            * self.radius
+            * Vector::new(
+                -phi.cos() * theta.cos(),
+                theta.sin(),
+                phi.sin() * theta.cos(),
+            );
+        rec.material = self.material.clone();
+        true
+    }
+}
+
+pub struct Triangle {
+    vertices: [Point; 3],
+    uvs: [(f64, f64); 3],
+    pub material: Option<Rc<dyn Material>>,
+}
+
+impl Triangle {
+    pub fn new(a: Point, b: Point, c: Point, material: Option<Rc<dyn Material>>) -> Self {
+        Triangle {
+            vertices: [a, b, c],
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            material,
+        }
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+        self.uvs = uvs;
+        self
+    }
+}
+
+impl Hittable for Triangle {
+    // Moller-Trumbore intersection.
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let [p0, p1, p2] = self.vertices;
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = Vector::cross(r.direction(), e2);
+        let det = Vector::dot(e1, pvec);
+        if det.abs() < 1e-12 {
+            return false;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin() - p0;
+        let b1 = Vector::dot(tvec, pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return false;
+        }
+        let qvec = Vector::cross(tvec, e1);
+        let b2 = Vector::dot(r.direction(), qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return false;
+        }
+        let t = Vector::dot(e2, qvec) * inv_det;
+        if !ray_t.surrounds(t) {
+            return false;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let [(u0, v0), (u1, v1), (u2, v2)] = self.uvs;
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = b0 * u0 + b1 * u1 + b2 * u2;
+        rec.v = b0 * v0 + b1 * v1 + b2 * v2;
+        let outward_normal = Vector::cross(e1, e2).unit();
         rec.set_face_normal(r, outward_normal);
+
+        // Tangents follow the direction of increasing u and v in the mesh uvs.
+        let (du1, dv1) = (u1 - u0, v1 - v0);
+        let (du2, dv2) = (u2 - u0, v2 - v0);
+        let uv_det = du1 * dv2 - dv1 * du2;
+        if uv_det.abs() < 1e-12 {
+            let onb = Onb::new(outward_normal);
+            rec.tangent = onb.u();
+            rec.bitangent = onb.v();
+        } else {
+            let inv = 1.0 / uv_det;
+            rec.tangent = (dv2 * e1 - dv1 * e2) * inv;
+            rec.bitangent = (du1 * e2 - du2 * e1) * inv;
+        }
         rec.material = self.material.clone();
-        return true;
+        true
     }
 }
 
+#[derive(Default)]
 pub struct HittableList {
     objects: Vec<Box<dyn Hittable>>,
 }
@@ -111,11 +248,12 @@ impl Hittable for HittableList {
         let mut hit_anything = false;
         let mut closest = ray_t.max;
 
-        for object in &self.objects {
+        for (i, object) in self.objects.iter().enumerate() {
             if object.hit(r, Interval::new(ray_t.min, closest), &mut tmp_record) {
                 hit_anything = true;
                 closest = tmp_record.t;
                 *rec = tmp_record.clone();
+                rec.object_id = i as u32 + 1;
             }
         }
 
@@ -0,0 +1,368 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::rtweeknd::{self, PI};
+
+// Light distribution of a fixture measured with a goniophotometer, read
+// from an IESNA LM-63 (.ies) file. Only type C photometry, the one used for
+// architectural and street lights, is supported: vertical angles are
+// measured from straight down and horizontal angles around that axis.
+pub struct IesProfile {
+    vertical: Vec<f64>,
+    horizontal: Vec<f64>,
+    // Candela per horizontal angle, each a row over the vertical angles.
+    candela: Vec<Vec<f64>>,
+    lumens: f64,
+}
+
+impl IesProfile {
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        // Keyword lines come before TILT, the numbers after it.
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix("TILT="))
+            .ok_or_else(|| invalid("missing TILT line"))?
+            .trim()
+            .to_string();
+        let mut numbers = Vec::new();
+        for line in lines {
+            for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+                if word.is_empty() {
+                    continue;
+                }
+                numbers.push(
+                    word.parse::<f64>()
+                        .map_err(|_| invalid(&format!("bad number '{}'", word)))?,
+                );
+            }
+        }
+        // Counts come from the file, so they have to be whole numbers no
+        // larger than the file before anything is sized by them.
+        let total = numbers.len() as f64;
+        let count = |value: f64| {
+            if value.is_finite() && value >= 0.0 && value.fract() == 0.0 && value <= total {
+                Ok(value as usize)
+            } else {
+                Err(invalid(&format!("bad count {}", value)))
+            }
+        };
+        let mut numbers = numbers.into_iter();
+        let mut next = || {
+            numbers
+                .next()
+                .ok_or_else(|| invalid("unexpected end of file"))
+        };
+
+        // Lamp output by tilt angle, only relevant to lamps mounted at an
+        // angle, so the table is skipped. TILT=<file> is treated as NONE.
+        if tilt == "INCLUDE" {
+            next()?;
+            let angles = count(next()?)?;
+            for _ in 0..2 * angles {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = count(next()?)?;
+        let horizontal_count = count(next()?)?;
+        let photometric_type = next()?;
+        let _units = next()?;
+        let _width = next()?;
+        let _length = next()?;
+        let _height = next()?;
+        let ballast_factor = next()?;
+        let _file_generation_type = next()?;
+        let _input_watts = next()?;
+        if photometric_type != 1.0 {
+            return Err(invalid("only type C photometry is supported"));
+        }
+        if vertical_count < 2 || horizontal_count < 1 {
+            return Err(invalid("too few angles"));
+        }
+
+        let mut read = |count: usize| (0..count).map(|_| next()).collect::<io::Result<Vec<_>>>();
+        let vertical = read(vertical_count)?;
+        let horizontal = read(horizontal_count)?;
+        let increasing = |angles: &[f64]| angles.windows(2).all(|w| w[0] < w[1]);
+        if !increasing(&vertical) || !increasing(&horizontal) {
+            return Err(invalid("angles must be increasing"));
+        }
+        let (first, last) = (horizontal[0], horizontal[horizontal_count - 1]);
+        if horizontal_count > 1 && first != 0.0 && (first != 90.0 || last != 270.0) {
+            return Err(invalid(
+                "horizontal angles must start at 0, or span 90 to 270",
+            ));
+        }
+        let mut candela = Vec::with_capacity(horizontal_count);
+        for _ in 0..horizontal_count {
+            let row = read(vertical_count)?;
+            candela.push(
+                row.iter()
+                    .map(|&cd| (cd * multiplier * ballast_factor).max(0.0))
+                    .collect(),
+            );
+        }
+
+        let mut profile = IesProfile {
+            vertical,
+            horizontal,
+            candela,
+            lumens: 0.0,
+        };
+        profile.lumens = profile.integrate();
+        Ok(profile)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        IesProfile::parse(&fs::read_to_string(path)?)
+    }
+
+    // Total light output of the fixture.
+    pub fn lumens(&self) -> f64 {
+        self.lumens
+    }
+
+    // Intensity at `vertical` degrees from straight down and `horizontal`
+    // degrees around it, interpolated bilinearly between the measured
+    // angles.
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let (v0, v1, tv) = match bracket(&self.vertical, vertical) {
+            Some(b) => b,
+            // Not measured, as on the upper half of a downlight.
+            None => return 0.0,
+        };
+        if self.horizontal.len() == 1 {
+            let row = &self.candela[0];
+            return (1.0 - tv) * row[v0] + tv * row[v1];
+        }
+        let horizontal = self.fold(horizontal);
+        let first = self.horizontal[0];
+        let last = self.horizontal[self.horizontal.len() - 1];
+        let (h0, h1, th) =
+            bracket(&self.horizontal, horizontal.clamp(first, last)).unwrap_or((0, 0, 0.0));
+        let at = |h: usize| (1.0 - tv) * self.candela[h][v0] + tv * self.candela[h][v1];
+        (1.0 - th) * at(h0) + th * at(h1)
+    }
+
+    // Horizontal angle mapped into the measured range using the symmetry
+    // the angle range implies: 0 to 90 for quadrants, 0 to 180 and 90 to 270
+    // for halves mirrored about those planes, and 0 to 360 for none.
+    fn fold(&self, horizontal: f64) -> f64 {
+        let h = horizontal.rem_euclid(360.0);
+        let first = self.horizontal[0];
+        let last = self.horizontal[self.horizontal.len() - 1];
+        if first == 90.0 {
+            if h < 90.0 {
+                180.0 - h
+            } else if h > 270.0 {
+                540.0 - h
+            } else {
+                h
+            }
+        } else if last <= 90.0 {
+            let h = if h > 180.0 { 360.0 - h } else { h };
+            if h > 90.0 {
+                180.0 - h
+            } else {
+                h
+            }
+        } else if last <= 180.0 {
+            if h > 180.0 {
+                360.0 - h
+            } else {
+                h
+            }
+        } else {
+            h
+        }
+    }
+
+    // Flux over the whole sphere by the midpoint rule.
+    fn integrate(&self) -> f64 {
+        const STEPS: usize = 180;
+        let d_theta = PI / STEPS as f64;
+        let d_phi = 2.0 * PI / (2 * STEPS) as f64;
+        let mut flux = 0.0;
+        for i in 0..STEPS {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..2 * STEPS {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let cd = self.candela(rtweeknd::rad2deg(theta), rtweeknd::rad2deg(phi));
+                flux += cd * theta.sin() * d_theta * d_phi;
+            }
+        }
+        flux
+    }
+}
+
+// Indices around `x` in increasing `angles` and the fraction between them.
+fn bracket(angles: &[f64], x: f64) -> Option<(usize, usize, f64)> {
+    let first = angles[0];
+    let last = angles[angles.len() - 1];
+    if x < first || x > last {
+        return None;
+    }
+    let i = angles
+        .partition_point(|&a| a <= x)
+        .clamp(1, angles.len() - 1);
+    let t = (x - angles[i - 1]) / (angles[i] - angles[i - 1]);
+    Some((i - 1, i, t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::{Light, Power, LUMENS_PER_WATT};
+    use crate::vector::{Point, Vector};
+    use std::rc::Rc;
+
+    // Profile with the given horizontal angles and one row of candela per
+    // angle over the vertical angles 0, 45 and 90.
+    fn profile(horizontal: &[f64], rows: &[[f64; 3]]) -> String {
+        let join = |values: &[f64]| {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut text = format!(
+            "IESNA:LM-63-2002\n[TEST] test\nTILT=NONE\n1 -1 1 3 {} 1 2 0 0 0\n1 1 10\n0 45 90\n{}\n",
+            horizontal.len(),
+            join(horizontal)
+        );
+        for row in rows {
+            text += &join(row);
+            text += "\n";
+        }
+        text
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn isotropic_lumens() {
+        let text = "TILT=NONE\n1 -1 1 3 1 1 2 0 0 0\n1 1 10\n0 90 180\n0\n100 100 100\n";
+        let profile = IesProfile::parse(text).unwrap();
+        assert!((profile.lumens() / (4.0 * PI * 100.0) - 1.0).abs() < 1e-3);
+        assert!(close(profile.candela(120.0, 33.0), 100.0));
+    }
+
+    #[test]
+    fn tilt_multiplier_and_quadrant_symmetry() {
+        let text = "IESNA91\nTILT=INCLUDE\n1\n3\n0 45 90\n1 0.9 0.8\n\
+                    1 1000 2.0 3 3 1 1 0.5 0.5 0.1\n0.5 1 50\n0 45 90\n0 45 90\n\
+                    500 200 0\n450 150 0\n250,100,0\n";
+        let profile = IesProfile::parse(text).unwrap();
+        // Multiplier 2 times ballast factor 0.5.
+        assert!(close(profile.candela(0.0, 0.0), 500.0));
+        assert!(close(profile.candela(22.5, 0.0), 350.0));
+        assert!(close(profile.candela(45.0, 22.5), 175.0));
+        for h in [135.0, 225.0, 315.0, -45.0] {
+            assert!(close(profile.candela(30.0, h), profile.candela(30.0, 45.0)));
+        }
+        assert_eq!(profile.candela(100.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn bilateral_symmetry() {
+        let text = profile(&[0.0, 90.0, 180.0], &[[100.0; 3], [200.0; 3], [300.0; 3]]);
+        let profile = IesProfile::parse(&text).unwrap();
+        assert!(close(profile.candela(10.0, 270.0), 200.0));
+        assert!(close(profile.candela(10.0, 315.0), 150.0));
+        assert!(close(
+            profile.candela(10.0, 200.0),
+            profile.candela(10.0, 160.0)
+        ));
+    }
+
+    #[test]
+    fn symmetry_about_the_90_270_plane() {
+        let text = profile(&[90.0, 180.0, 270.0], &[[100.0; 3], [200.0; 3], [300.0; 3]]);
+        let profile = IesProfile::parse(&text).unwrap();
+        assert!(close(profile.candela(10.0, 0.0), 200.0));
+        assert!(close(profile.candela(10.0, 45.0), 150.0));
+        assert!(close(
+            profile.candela(10.0, 300.0),
+            200.0 + 100.0 * 60.0 / 90.0
+        ));
+        assert!(close(
+            profile.candela(10.0, 10.0),
+            profile.candela(10.0, 170.0)
+        ));
+        assert!(close(
+            profile.candela(10.0, 350.0),
+            profile.candela(10.0, 190.0)
+        ));
+    }
+
+    #[test]
+    fn full_circle() {
+        let rows = [[100.0; 3], [200.0; 3], [300.0; 3], [400.0; 3], [100.0; 3]];
+        let text = profile(&[0.0, 90.0, 180.0, 270.0, 360.0], &rows);
+        let profile = IesProfile::parse(&text).unwrap();
+        assert!(close(profile.candela(10.0, 225.0), 350.0));
+        assert!(close(profile.candela(10.0, -90.0), 400.0));
+    }
+
+    #[test]
+    fn light_with_profile_keeps_the_fixture_output() {
+        let text = profile(&[0.0, 90.0], &[[800.0, 400.0, 0.0], [600.0, 200.0, 0.0]]);
+        let profile = Rc::new(IesProfile::parse(&text).unwrap());
+        let light = Light::point(
+            Point::new(0.0, 0.0, 0.0),
+            Vector::new(1.0, 1.0, 1.0),
+            Power::Lumens(profile.lumens()),
+        )
+        .with_profile(
+            profile.clone(),
+            Vector::new(0.0, -1.0, 0.0),
+            Vector::new(1.0, 0.0, 0.0),
+        );
+        // At distance 1 the irradiance is the intensity, 45 degrees from
+        // straight down in the 0 and 90 degree planes.
+        let s = std::f64::consts::FRAC_1_SQRT_2;
+        let samples = [
+            (Point::new(0.0, -1.0, 0.0), 800.0),
+            (Point::new(s, -s, 0.0), 400.0),
+            (Point::new(0.0, -s, -s), 200.0),
+        ];
+        for (p, candela) in samples {
+            let (_, distance, irradiance) = light.incident(p).unwrap();
+            assert!((distance - 1.0).abs() < 1e-9);
+            assert!((irradiance.y() * LUMENS_PER_WATT / candela - 1.0).abs() < 1e-9);
+        }
+        assert!(light.incident(Point::new(0.0, 1.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn rejects_unsupported_files() {
+        let type_b = "TILT=NONE\n1 -1 1 2 1 2 1 0 0 0\n1 1 10\n0 90\n0\n1 1\n";
+        assert!(IesProfile::parse(type_b).is_err());
+        let odd_range = profile(&[45.0, 90.0], &[[1.0; 3], [1.0; 3]]);
+        assert!(IesProfile::parse(&odd_range).is_err());
+        assert!(IesProfile::parse("no tilt line").is_err());
+        let truncated = "TILT=NONE\n1 -1 1 3 1 1 2 0 0 0\n1 1 10\n0 90 180\n0\n100 100\n";
+        assert!(IesProfile::parse(truncated).is_err());
+        for bad in ["-3", "NaN", "1e300", "2.5"] {
+            let tilt = format!("TILT=INCLUDE\n1 {}\n", bad);
+            assert!(IesProfile::parse(&tilt).is_err());
+            let counts = format!(
+                "TILT=NONE\n1 -1 1 {} 1 1 2 0 0 0\n1 1 10\n0 90\n0\n1 1\n",
+                bad
+            );
+            let err = IesProfile::parse(&counts).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
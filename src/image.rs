@@ -0,0 +1,186 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::color::{self, Color};
+
+// Grid of colors, either decoded from a file with components in [0,1] as
+// stored, or linear radiance produced by the renderer.
+#[derive(Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    data: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, data: Vec<Color>) -> Self {
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    // Reads binary (P6) or ASCII (P3) PPM and PGM (P5/P2) files.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        Self::parse_pnm(&bytes)
+    }
+
+    fn parse_pnm(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut pos = 0;
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            // Skip whitespace and comments.
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+                if bytes[pos] == b'#' {
+                    while pos < bytes.len() && bytes[pos] != b'\n' {
+                        pos += 1;
+                    }
+                } else {
+                    pos += 1;
+                }
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated header"));
+            }
+            header.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+        }
+        // Exactly one whitespace byte separates the header from binary data.
+        pos += 1;
+
+        let channels = match header[0].as_str() {
+            "P2" | "P5" => 1,
+            "P3" | "P6" => 3,
+            _ => return Err(invalid("unsupported image format")),
+        };
+        let parse = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad header value"));
+        let width = parse(&header[1])?;
+        let height = parse(&header[2])?;
+        let maxval = parse(&header[3])?.max(1);
+
+        // The header is untrusted, so sizes must not overflow.
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(channels))
+            .ok_or_else(|| invalid("image too large"))?;
+        let values: Vec<f64> = if header[0] == "P5" || header[0] == "P6" {
+            let size = if maxval > 255 { 2 } else { 1 };
+            let end = count
+                .checked_mul(size)
+                .and_then(|n| n.checked_add(pos))
+                .ok_or_else(|| invalid("image too large"))?;
+            let raw = bytes
+                .get(pos..end)
+                .ok_or_else(|| invalid("truncated pixel data"))?;
+            raw.chunks(size)
+                .map(|c| {
+                    let v = if size == 2 {
+                        (c[0] as usize) << 8 | c[1] as usize
+                    } else {
+                        c[0] as usize
+                    };
+                    v as f64 / maxval as f64
+                })
+                .collect()
+        } else {
+            let text = String::from_utf8_lossy(bytes.get(pos..).unwrap_or(&[]));
+            text.split_ascii_whitespace()
+                .take(count)
+                .map(|s| parse(s).map(|v| v as f64 / maxval as f64))
+                .collect::<io::Result<_>>()?
+        };
+        if values.len() < count {
+            return Err(invalid("truncated pixel data"));
+        }
+
+        let data = values
+            .chunks(channels)
+            .map(|c| {
+                if channels == 1 {
+                    Color::new(c[0], c[0], c[0])
+                } else {
+                    Color::new(c[0], c[1], c[2])
+                }
+            })
+            .collect();
+        Ok(Image::new(width, height, data))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let x = x.min(self.width - 1);
+        let y = y.min(self.height - 1);
+        self.data[y * self.width + x]
+    }
+
+    pub fn map(&mut self, f: impl Fn(Color) -> Color) {
+        for c in self.data.iter_mut() {
+            *c = f(*c);
+        }
+    }
+
+    // Writes linear colors gamma encoded to an ASCII PPM.
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "P3")?;
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "255")?;
+        for &c in &self.data {
+            color::write_color(out, c)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_ppm(&mut out)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_binary_and_ascii_pnm() {
+        let mut p6 = b"P6\n# comment\n2 1\n255\n".to_vec();
+        p6.extend_from_slice(&[255, 0, 0, 0, 51, 255]);
+        let image = Image::parse_pnm(&p6).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.pixel(0, 0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(image.pixel(1, 0), Color::new(0.0, 0.2, 1.0));
+
+        let p2 = Image::parse_pnm(b"P2 1 2 4\n0 2").unwrap();
+        assert_eq!(p2.pixel(0, 1), Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn rejects_overflowing_sizes() {
+        let huge = format!("P6 {} {} 255\n", usize::MAX / 2, 3);
+        let err = Image::parse_pnm(huge.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let wraps = format!("P5 {} 1 65535\n", usize::MAX / 2 + 1);
+        let err = Image::parse_pnm(wraps.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        assert!(Image::parse_pnm(b"P6 2 2 255\n\x00\x00\x00").is_err());
+    }
+}
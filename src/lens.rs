@@ -0,0 +1,594 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::material::Dispersion;
+use crate::ray::Ray;
+use crate::vector::{Point, Vector};
+
+// One refracting surface of a lens prescription, front (scene side) first.
+// Lengths are in millimeters.
+#[derive(Clone, Copy, Debug)]
+pub struct LensElement {
+    // Signed radius of curvature, positive when the center lies towards the
+    // film. 0 marks the flat aperture stop.
+    pub radius: f64,
+    // Distance along the axis to the next surface, or to the film for the
+    // last one.
+    pub thickness: f64,
+    // Index of refraction at the d-line of the glass behind the surface,
+    // 1 (or 0) for air.
+    pub ior: f64,
+    // Abbe number of that glass, 0 for no dispersion.
+    pub abbe: f64,
+    pub aperture_radius: f64,
+}
+
+impl LensElement {
+    fn refraction_index(&self, wavelength: Option<f64>) -> f64 {
+        if self.ior <= 0.0 {
+            return 1.0;
+        }
+        match wavelength {
+            Some(lambda) if self.abbe > 0.0 => {
+                Dispersion::from_abbe(self.ior, self.abbe).refraction_index(lambda)
+            }
+            _ => self.ior,
+        }
+    }
+
+    fn is_stop(&self) -> bool {
+        self.radius == 0.0
+    }
+}
+
+// Bounds of the exit pupil on the rear element plane, for film points on
+// the +x axis.
+#[derive(Clone, Copy, Debug)]
+struct Bounds {
+    min: (f64, f64),
+    max: (f64, f64),
+}
+
+impl Bounds {
+    fn area(&self) -> f64 {
+        (self.max.0 - self.min.0).max(0.0) * (self.max.1 - self.min.1).max(0.0)
+    }
+}
+
+// Film positions the exit pupil is tabulated for, between the center and
+// the corner of the film.
+const PUPIL_INTERVALS: usize = 64;
+// Grid of candidate points on the rear element per interval.
+const PUPIL_GRID: usize = 128;
+
+// Camera that traces rays through a system of spherical lens elements, after
+// Kolb et al., "A Realistic Camera Model for Computer Graphics" (1995) and
+// the implementation in pbrt. Distortion, vignetting and, on spectral
+// paths, chromatic aberration all follow from the prescription.
+//
+// Lens space is camera space in millimeters: the film is at z = 0 and the
+// lens looks down -z.
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    // Diagonal of the film, 43.3 for full frame 35mm.
+    pub film_diagonal: f64,
+    // Scene units per millimeter, 0.001 for scenes modelled in meters.
+    pub scale: f64,
+    exit_pupils: Vec<Bounds>,
+    // Exposure of the film center, image brightness is relative to it.
+    axis_exposure: f64,
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> Self {
+        LensSystem {
+            elements,
+            film_diagonal: 43.3,
+            scale: 0.001,
+            exit_pupils: Vec::new(),
+            axis_exposure: 0.0,
+        }
+    }
+
+    // Prescription in the format of pbrt's lens files: one surface per line
+    // with radius, thickness, index of refraction and aperture diameter in
+    // millimeters, and an optional Abbe number. '#' starts a comment.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut elements = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            if line.trim().is_empty() {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid(format!("line {}: not a number", number + 1)))?;
+            if values.len() < 4 || values.len() > 5 {
+                return Err(invalid(format!(
+                    "line {}: expected 4 or 5 values, found {}",
+                    number + 1,
+                    values.len()
+                )));
+            }
+            elements.push(LensElement {
+                radius: values[0],
+                thickness: values[1],
+                ior: values[2],
+                aperture_radius: values[3] / 2.0,
+                abbe: values.get(4).copied().unwrap_or(0.0),
+            });
+        }
+        if elements.is_empty() {
+            return Err(invalid("no lens elements".to_string()));
+        }
+        Ok(LensSystem::new(elements))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        LensSystem::parse(&fs::read_to_string(path)?)
+    }
+
+    // 50mm f/2 double Gauss, US patent 2,673,491 (Tronnier) as scaled in
+    // pbrt's lens collection. The Abbe numbers are typical for glasses of
+    // these indices.
+    pub fn double_gauss_50mm() -> Self {
+        let element = |radius, thickness, ior, diameter: f64, abbe| LensElement {
+            radius,
+            thickness,
+            ior,
+            abbe,
+            aperture_radius: diameter / 2.0,
+        };
+        LensSystem::new(vec![
+            element(29.475, 3.76, 1.67, 25.2, 47.2),
+            element(84.83, 0.12, 1.0, 25.2, 0.0),
+            element(19.275, 4.025, 1.67, 23.0, 47.2),
+            element(40.77, 3.275, 1.699, 23.0, 30.1),
+            element(12.75, 5.705, 1.0, 18.0, 0.0),
+            element(0.0, 4.5, 0.0, 17.1, 0.0),
+            element(-14.495, 1.18, 1.603, 17.0, 38.0),
+            element(40.77, 6.065, 1.658, 20.0, 57.3),
+            element(-20.385, 0.19, 1.0, 20.0, 0.0),
+            element(437.065, 3.22, 1.717, 20.0, 47.9),
+            element(-39.73, 0.0, 1.0, 20.0, 0.0),
+        ])
+    }
+
+    // Opens or closes the aperture stop, e.g. to stop down the lens. The
+    // diameter is in millimeters.
+    pub fn with_aperture_diameter(mut self, diameter: f64) -> Self {
+        if let Some(stop) = self.elements.iter_mut().find(|e| e.is_stop()) {
+            stop.aperture_radius = diameter / 2.0;
+        }
+        self
+    }
+
+    // Sizes the aperture stop so that the entrance pupil, the stop as seen
+    // from the front, is the focal length over `f_number` across.
+    pub fn set_f_number(&mut self, f_number: f64) {
+        let Some(((p0, f0), _)) = self.cardinal_points() else {
+            return;
+        };
+        let target = (f0 - p0) / f_number / 2.0;
+        // The pupil scales with the stop, up to where other elements clip it.
+        for _ in 0..4 {
+            let radius = self.entrance_pupil_radius();
+            let Some(stop) = self.elements.iter_mut().find(|e| e.is_stop()) else {
+                return;
+            };
+            if radius <= 0.0 {
+                return;
+            }
+            stop.aperture_radius *= target / radius;
+        }
+    }
+
+    // Moves the lens group so that objects `distance` scene units in front
+    // of the film are sharp, using the thick lens approximation, and
+    // tabulates the exit pupil for the new position.
+    pub fn focus(&mut self, distance: f64) {
+        if let Some(thickness) = self.focus_thick_lens(distance / self.scale) {
+            if let Some(rear) = self.elements.last_mut() {
+                rear.thickness = thickness;
+            }
+        } else {
+            eprintln!("Warning: the lens cannot focus at {}", distance);
+        }
+        self.exit_pupils = (0..PUPIL_INTERVALS)
+            .map(|i| {
+                let r = self.film_diagonal / 2.0;
+                self.bound_exit_pupil(
+                    r * i as f64 / PUPIL_INTERVALS as f64,
+                    r * (i + 1) as f64 / PUPIL_INTERVALS as f64,
+                )
+            })
+            .collect();
+        self.axis_exposure = self.axis_exposure();
+    }
+
+    // Ray leaving the front of the lens for film position (x, y) in [0, 1),
+    // top left origin, in camera space scene units, with its exposure
+    // relative to the center of the film. None if the lens blocks it.
+    pub fn camera_ray(
+        &self,
+        x: f64,
+        y: f64,
+        aspect: f64,
+        u: (f64, f64),
+        wavelength: Option<f64>,
+    ) -> Option<(Point, Vector, f64)> {
+        let diagonal = (aspect * aspect + 1.0).sqrt();
+        let width = self.film_diagonal * aspect / diagonal;
+        let height = self.film_diagonal / diagonal;
+        // The lens forms an inverted image on the film.
+        let film = Point::new((0.5 - x) * width, (y - 0.5) * height, 0.0);
+
+        let (pupil, area) = self.sample_exit_pupil(film.x(), film.y(), u)?;
+        let ray = Ray::new(film, pupil - film);
+        let out = self.trace_from_film(&ray, wavelength)?;
+
+        // Irradiance on the film falls off with the fourth power of the
+        // cosine to the axis (Kolb et al.).
+        let cos_theta = ray.direction().unit().z().abs();
+        let weight = cos_theta.powi(4) * area / self.axis_exposure;
+        Some((self.scale * out.origin(), out.direction(), weight))
+    }
+
+    fn rear_z(&self) -> f64 {
+        -self.elements.last().map_or(0.0, |e| e.thickness)
+    }
+
+    fn front_z(&self) -> f64 {
+        -self.elements.iter().map(|e| e.thickness).sum::<f64>()
+    }
+
+    fn rear_aperture(&self) -> f64 {
+        self.elements.last().map_or(0.0, |e| e.aperture_radius)
+    }
+
+    // Traces a ray from the film through the elements, back to front.
+    fn trace_from_film(&self, ray: &Ray, wavelength: Option<f64>) -> Option<Ray> {
+        let mut origin = ray.origin();
+        let mut direction = ray.direction();
+        let mut element_z = 0.0;
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            element_z -= element.thickness;
+            let (t, normal) = intersect_element(element, element_z, origin, direction)?;
+            let hit = origin + t * direction;
+            if hit.x() * hit.x() + hit.y() * hit.y() > element.aperture_radius.powi(2) {
+                return None;
+            }
+            origin = hit;
+            if !element.is_stop() {
+                let eta_i = element.refraction_index(wavelength);
+                let eta_t = if i > 0 {
+                    self.elements[i - 1].refraction_index(wavelength)
+                } else {
+                    1.0
+                };
+                direction = refract(-direction.unit(), normal, eta_i / eta_t)?;
+            }
+        }
+        Some(Ray::new(origin, direction))
+    }
+
+    // Traces a ray from the scene through the elements, front to back.
+    fn trace_from_scene(&self, ray: &Ray) -> Option<Ray> {
+        let mut origin = ray.origin();
+        let mut direction = ray.direction();
+        let mut element_z = self.front_z();
+        for i in 0..self.elements.len() {
+            let element = &self.elements[i];
+            let (t, normal) = intersect_element(element, element_z, origin, direction)?;
+            let hit = origin + t * direction;
+            if hit.x() * hit.x() + hit.y() * hit.y() > element.aperture_radius.powi(2) {
+                return None;
+            }
+            origin = hit;
+            if !element.is_stop() {
+                let eta_i = if i > 0 {
+                    self.elements[i - 1].refraction_index(None)
+                } else {
+                    1.0
+                };
+                let eta_t = element.refraction_index(None);
+                direction = refract(-direction.unit(), normal, eta_i / eta_t)?;
+            }
+            element_z += element.thickness;
+        }
+        Some(Ray::new(origin, direction))
+    }
+
+    // Film side (first) and scene side (second) principal plane and focal
+    // point z, traced with rays parallel to the axis.
+    fn cardinal_points(&self) -> Option<((f64, f64), (f64, f64))> {
+        let x = 0.001 * self.film_diagonal;
+        let scene = Ray::new(
+            Point::new(x, 0.0, self.front_z() - 1.0),
+            Vector::new(0.0, 0.0, 1.0),
+        );
+        let film_side = cardinal_point(&scene, &self.trace_from_scene(&scene)?);
+        let film = Ray::new(
+            Point::new(x, 0.0, self.rear_z() + 1.0),
+            Vector::new(0.0, 0.0, -1.0),
+        );
+        let scene_side = cardinal_point(&film, &self.trace_from_film(&film, None)?);
+        Some((film_side, scene_side))
+    }
+
+    // Rear element to film distance that focuses at `distance` millimeters.
+    fn focus_thick_lens(&self, distance: f64) -> Option<f64> {
+        let ((p0, f0), (p1, _)) = self.cardinal_points()?;
+        // Focal length, then the thick lens equation solved for the shift
+        // of the lens group.
+        let f = f0 - p0;
+        let z = -distance;
+        let c = (p1 - z - p0) * (p1 - z - 4.0 * f - p0);
+        if c < 0.0 {
+            return None;
+        }
+        let delta = 0.5 * (p1 - z + p0 - c.sqrt());
+        let thickness = -self.rear_z() + delta;
+        (thickness > 0.0).then_some(thickness)
+    }
+
+    // Largest height of a ray parallel to the axis that makes it through.
+    fn entrance_pupil_radius(&self) -> f64 {
+        let (mut low, mut high) = (0.0, self.elements[0].aperture_radius);
+        for _ in 0..32 {
+            let mid = 0.5 * (low + high);
+            let ray = Ray::new(
+                Point::new(mid, 0.0, self.front_z() - 1.0),
+                Vector::new(0.0, 0.0, 1.0),
+            );
+            if self.trace_from_scene(&ray).is_some() {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    // Bounding box of the points on the rear element that rays from film
+    // points between r0 and r1 on the x axis pass through the lens from.
+    fn bound_exit_pupil(&self, r0: f64, r1: f64) -> Bounds {
+        let extent = 1.5 * self.rear_aperture();
+        let rear_z = self.rear_z();
+        let n = PUPIL_GRID * PUPIL_GRID;
+        let mut bounds = Bounds {
+            min: (f64::INFINITY, f64::INFINITY),
+            max: (f64::NEG_INFINITY, f64::NEG_INFINITY),
+        };
+        for i in 0..n {
+            let film_x = r0 + (r1 - r0) * (i as f64 + 0.5) / n as f64;
+            let film = Point::new(film_x, 0.0, 0.0);
+            let px = -extent + 2.0 * extent * ((i % PUPIL_GRID) as f64 + 0.5) / PUPIL_GRID as f64;
+            let py = -extent + 2.0 * extent * ((i / PUPIL_GRID) as f64 + 0.5) / PUPIL_GRID as f64;
+            let rear = Point::new(px, py, rear_z);
+            if self
+                .trace_from_film(&Ray::new(film, rear - film), None)
+                .is_some()
+            {
+                bounds.min = (bounds.min.0.min(px), bounds.min.1.min(py));
+                bounds.max = (bounds.max.0.max(px), bounds.max.1.max(py));
+            }
+        }
+        if bounds.min.0 > bounds.max.0 {
+            // Nothing passes, no light reaches this part of the film.
+            return Bounds {
+                min: (0.0, 0.0),
+                max: (0.0, 0.0),
+            };
+        }
+        // Grow by a grid cell to be safe from missed points.
+        let cell = 2.0 * extent / PUPIL_GRID as f64;
+        Bounds {
+            min: (
+                (bounds.min.0 - cell).max(-extent),
+                (bounds.min.1 - cell).max(-extent),
+            ),
+            max: (
+                (bounds.max.0 + cell).min(extent),
+                (bounds.max.1 + cell).min(extent),
+            ),
+        }
+    }
+
+    // Expected weight of a ray from the film center before normalization.
+    fn axis_exposure(&self) -> f64 {
+        let Some(bounds) = self.exit_pupils.first() else {
+            return 0.0;
+        };
+        let mut sum = 0.0;
+        for i in 0..PUPIL_GRID {
+            for j in 0..PUPIL_GRID {
+                let u = (
+                    (i as f64 + 0.5) / PUPIL_GRID as f64,
+                    (j as f64 + 0.5) / PUPIL_GRID as f64,
+                );
+                let film = Point::zero();
+                let Some((pupil, _)) = self.sample_exit_pupil(0.0, 0.0, u) else {
+                    continue;
+                };
+                let ray = Ray::new(film, pupil - film);
+                if self.trace_from_film(&ray, None).is_some() {
+                    sum += ray.direction().unit().z().powi(4);
+                }
+            }
+        }
+        sum / (PUPIL_GRID * PUPIL_GRID) as f64 * bounds.area()
+    }
+
+    // Point on the rear element plane in the exit pupil of film point
+    // (x, y), and the area it was sampled from.
+    fn sample_exit_pupil(&self, x: f64, y: f64, u: (f64, f64)) -> Option<(Point, f64)> {
+        let r = (x * x + y * y).sqrt();
+        let index = (r / (self.film_diagonal / 2.0) * PUPIL_INTERVALS as f64) as usize;
+        let bounds = self.exit_pupils.get(index.min(PUPIL_INTERVALS - 1))?;
+        let area = bounds.area();
+        if area <= 0.0 {
+            return None;
+        }
+        let px = bounds.min.0 + u.0 * (bounds.max.0 - bounds.min.0);
+        let py = bounds.min.1 + u.1 * (bounds.max.1 - bounds.min.1);
+        // Rotate from the +x axis to the film point.
+        let (sin, cos) = if r > 0.0 { (y / r, x / r) } else { (0.0, 1.0) };
+        Some((
+            Point::new(cos * px - sin * py, sin * px + cos * py, self.rear_z()),
+            area,
+        ))
+    }
+}
+
+// Distance along the ray to a lens surface at `z` on the axis and the
+// normal there, facing against the ray.
+fn intersect_element(
+    element: &LensElement,
+    z: f64,
+    origin: Point,
+    direction: Vector,
+) -> Option<(f64, Vector)> {
+    if element.is_stop() {
+        let t = (z - origin.z()) / direction.z();
+        return (t >= 0.0).then_some((t, Vector::new(0.0, 0.0, -direction.z().signum())));
+    }
+    let center = Point::new(0.0, 0.0, z + element.radius);
+    let oc = origin - center;
+    let a = direction.length_squared();
+    let half_b = Vector::dot(oc, direction);
+    let c = oc.length_squared() - element.radius * element.radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (t0, t1) = ((-half_b - root) / a, (-half_b + root) / a);
+    // Of the two intersections with the sphere, the surface is the one on
+    // the axis side of the center.
+    let closer = (direction.z() > 0.0) != (element.radius < 0.0);
+    let t = if closer { t0.min(t1) } else { t0.max(t1) };
+    if t < 0.0 {
+        return None;
+    }
+    let mut normal = (oc + t * direction).unit();
+    if Vector::dot(normal, direction) > 0.0 {
+        normal = -normal;
+    }
+    Some((t, normal))
+}
+
+// Refraction of `wi`, pointing away from the surface on the side of the
+// normal, or None on total internal reflection.
+fn refract(wi: Vector, normal: Vector, eta: f64) -> Option<Vector> {
+    let cos_i = Vector::dot(normal, wi);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(eta * -wi + (eta * cos_i - cos_t) * normal)
+}
+
+// z of the principal plane and the focal point from a ray parallel to the
+// axis and the ray it leaves the lens as.
+fn cardinal_point(parallel: &Ray, out: &Ray) -> (f64, f64) {
+    let focal = out.at(-out.origin().x() / out.direction().x()).z();
+    let principal = out
+        .at((parallel.origin().x() - out.origin().x()) / out.direction().x())
+        .z();
+    (principal, focal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // z where a ray leaving the lens crosses the axis in the x-z plane.
+    fn axis_crossing(ray: &Ray) -> f64 {
+        ray.at(-ray.origin().x() / ray.direction().x()).z()
+    }
+
+    #[test]
+    fn parses_prescriptions() {
+        let lens = LensSystem::parse(
+            "# radius thickness ior diameter abbe\n\
+             50 5 1.5 20 64.2\n\
+             \n\
+             0 2 0 10 # stop\n\
+             -50 40 1 20\n",
+        )
+        .unwrap();
+        assert_eq!(lens.elements.len(), 3);
+        assert_eq!(lens.elements[0].abbe, 64.2);
+        assert_eq!(lens.elements[1].aperture_radius, 5.0);
+        assert!(lens.elements[1].is_stop());
+        assert_eq!(lens.elements[2].abbe, 0.0);
+
+        for text in [
+            "",
+            "# only a comment\n",
+            "50 5 1.5\n",
+            "50 5 1.5 20 60 1\n",
+            "50 x 1.5 20\n",
+        ] {
+            let err = LensSystem::parse(text).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn double_gauss_focal_length() {
+        let lens = LensSystem::double_gauss_50mm();
+        let ((p0, f0), (p1, f1)) = lens.cardinal_points().unwrap();
+        assert!((f0 - p0 - 50.0).abs() < 1.0, "{}", f0 - p0);
+        assert!((p1 - f1 - 50.0).abs() < 1.0, "{}", p1 - f1);
+    }
+
+    #[test]
+    fn double_gauss_focuses_at_distance() {
+        let mut lens = LensSystem::double_gauss_50mm();
+        lens.focus(2.0);
+        // Near axis rays from a point 2m in front of the film come back
+        // together on the film.
+        for height in [0.5, 1.0, 2.0] {
+            let object = Point::new(0.0, 0.0, -2000.0);
+            let target = Point::new(height, 0.0, lens.front_z());
+            let out = lens
+                .trace_from_scene(&Ray::new(object, target - object))
+                .unwrap();
+            let z = axis_crossing(&out);
+            assert!(z.abs() < 0.05, "height {}: image at {}", height, z);
+        }
+
+        // Focusing closer moves the lens away from the film.
+        let far = lens.elements.last().unwrap().thickness;
+        lens.focus(0.5);
+        assert!(lens.elements.last().unwrap().thickness > far);
+    }
+
+    #[test]
+    fn center_of_film_has_unit_exposure() {
+        let mut lens = LensSystem::double_gauss_50mm();
+        lens.focus(2.0);
+        // Blocked rays count as zero, the mean weight is one.
+        let n = 32;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                if let Some((origin, direction, weight)) = lens.camera_ray(0.5, 0.5, 1.5, u, None) {
+                    assert!(origin.z() < 0.0 && direction.z() < 0.0);
+                    sum += weight;
+                }
+            }
+        }
+        let mean = sum / (n * n) as f64;
+        assert!((mean - 1.0).abs() < 0.05, "{}", mean);
+    }
+}
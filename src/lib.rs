@@ -0,0 +1,30 @@
+pub mod aov;
+pub mod aperture;
+pub mod camera;
+pub mod color;
+pub mod colorspace;
+pub mod denoise;
+pub mod exposure;
+pub mod exr;
+pub mod filter;
+pub mod framebuffer;
+pub mod hittable;
+pub mod ies;
+pub mod image;
+pub mod interval;
+pub mod lens;
+pub mod light;
+pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod onb;
+pub mod projection;
+pub mod ray;
+pub mod rtweeknd;
+pub mod sampler;
+pub mod sky;
+pub mod spectrum;
+pub mod texture;
+pub mod thinfilm;
+pub mod tonemap;
+pub mod vector;
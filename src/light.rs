@@ -0,0 +1,296 @@
+use std::rc::Rc;
+
+use crate::color::{self, Color};
+use crate::colorspace::ColorSpace;
+use crate::ies::IesProfile;
+use crate::rtweeknd::{self, PI};
+use crate::spectrum::{self, LAMBDA_MAX, LAMBDA_MIN};
+use crate::vector::{Point, Vector};
+
+// Photometric units are converted to the renderer's radiometric ones with
+// the peak luminous efficacy, as if all light was at 555nm. A color of
+// luminance 1 is then 683 nits.
+pub const LUMENS_PER_WATT: f64 = 683.0;
+
+// Linear sRGB color of a black body at `kelvin`, e.g. 2700 for a warm
+// incandescent bulb or 6500 for daylight, with luminance 1.
+pub fn blackbody(kelvin: f64) -> Color {
+    let steps = 200;
+    let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut xyz = Vector::zero();
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * dl;
+        xyz += planck(lambda, kelvin) * spectrum::cie_xyz(lambda);
+    }
+    if xyz.y() <= 0.0 {
+        return Color::zero();
+    }
+    let rgb = spectrum::xyz_to_rgb(xyz / xyz.y());
+    // Very low temperatures lie outside the sRGB gamut.
+    let rgb = Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0));
+    rgb / color::luminance(rgb)
+}
+
+// Planck's law up to a constant factor, which the normalization removes.
+fn planck(lambda_nm: f64, kelvin: f64) -> f64 {
+    const C2: f64 = 1.4387769e-2;
+    let l = lambda_nm * 1e-9;
+    1.0 / (l.powi(5) * ((C2 / (l * kelvin.max(1.0))).exp() - 1.0))
+}
+
+// Output of a lamp.
+#[derive(Clone, Copy, Debug)]
+pub enum Power {
+    // Radiant and luminous flux, all light leaving the lamp.
+    Watts(f64),
+    Lumens(f64),
+    // Luminous intensity, the flux per steradian along the brightest
+    // direction.
+    Candela(f64),
+}
+
+// Light arriving at a surface facing the source, per square meter.
+#[derive(Clone, Copy, Debug)]
+pub enum Irradiance {
+    WattsPerSquareMeter(f64),
+    Lux(f64),
+}
+
+impl Irradiance {
+    pub fn watts_per_square_meter(self) -> f64 {
+        match self {
+            Irradiance::WattsPerSquareMeter(e) => e,
+            Irradiance::Lux(lux) => lux / LUMENS_PER_WATT,
+        }
+    }
+}
+
+// Brightness of an emitting surface, in W/(sr m^2) or nits (cd/m^2).
+pub fn nits(luminance: f64) -> f64 {
+    luminance / LUMENS_PER_WATT
+}
+
+// Radiance of a diffuse emitter of the given area (m^2) giving off `power`.
+pub fn surface_radiance(power: Power, area: f64) -> f64 {
+    let flux = match power {
+        Power::Watts(w) => w,
+        Power::Lumens(lm) => lm / LUMENS_PER_WATT,
+        // A Lambertian emitter is brightest along its normal.
+        Power::Candela(cd) => cd / LUMENS_PER_WATT * PI,
+    };
+    flux / (PI * area.max(1e-12))
+}
+
+// Analytic lights, infinitely small or far away. They are not visible to
+// camera rays and light surfaces through next event estimation.
+#[derive(Clone)]
+pub enum Light {
+    // Light from a point, equally in all directions unless it has a
+    // profile. Intensity in W/sr.
+    Point {
+        position: Point,
+        intensity: Color,
+        profile: Option<Photometry>,
+    },
+    // Cone of light around `direction`, at full intensity up to `inner`
+    // degrees off the axis and fading out at `outer`.
+    Spot {
+        position: Point,
+        direction: Vector,
+        intensity: Color,
+        inner: f64,
+        outer: f64,
+        profile: Option<Photometry>,
+    },
+    // Parallel light travelling along `direction`, like sunlight. Irradiance
+    // in W/m^2 on a surface facing it.
+    Directional {
+        direction: Vector,
+        irradiance: Color,
+    },
+}
+
+impl Light {
+    // `color` in linear sRGB sets the tint only, it is scaled to luminance 1.
+    pub fn point(position: Point, color: Color, power: Power) -> Self {
+        Light::Point {
+            position,
+            intensity: normalized(color) * intensity(power, 4.0 * PI),
+            profile: None,
+        }
+    }
+
+    pub fn spot(
+        position: Point,
+        direction: Vector,
+        inner: f64,
+        outer: f64,
+        color: Color,
+        power: Power,
+    ) -> Self {
+        let outer = outer.max(inner);
+        let cos_inner = rtweeknd::deg2rad(inner).cos();
+        let cos_outer = rtweeknd::deg2rad(outer).cos();
+        // Solid angle of the cone, counting the falloff as half.
+        let solid_angle = 2.0 * PI * ((1.0 - cos_inner) + (cos_inner - cos_outer) / 2.0);
+        Light::Spot {
+            position,
+            direction: direction.unit(),
+            intensity: normalized(color) * intensity(power, solid_angle),
+            inner,
+            outer,
+            profile: None,
+        }
+    }
+
+    pub fn directional(direction: Vector, color: Color, irradiance: Irradiance) -> Self {
+        Light::Directional {
+            direction: direction.unit(),
+            irradiance: normalized(color) * irradiance.watts_per_square_meter(),
+        }
+    }
+
+    // Light colors are given in linear sRGB, like blackbody's. This is the
+    // light with its color converted to `space`.
+    pub fn in_space(mut self, space: ColorSpace) -> Self {
+        match &mut self {
+            Light::Point { intensity, .. } | Light::Spot { intensity, .. } => {
+                *intensity = space.convert_srgb(*intensity)
+            }
+            Light::Directional { irradiance, .. } => *irradiance = space.convert_srgb(*irradiance),
+        }
+        self
+    }
+
+    // Shapes the light of a point or spot light by a measured fixture
+    // distribution, with the profile's straight down along `nadir` and its
+    // 0 degree horizontal plane towards `c0`. The light keeps its flux, so
+    // Power::Lumens(profile.lumens()) gives the fixture's own output. Spot
+    // cones still apply on top of the profile, and directional lights are
+    // left as they are.
+    pub fn with_profile(mut self, profile: Rc<IesProfile>, nadir: Vector, c0: Vector) -> Self {
+        let photometry = Photometry::new(profile, nadir, c0);
+        match &mut self {
+            Light::Point { profile, .. } | Light::Spot { profile, .. } => {
+                *profile = Some(photometry)
+            }
+            Light::Directional { .. } => {}
+        }
+        self
+    }
+
+    // Unit direction towards the light from `p`, its distance and the
+    // irradiance it gives on a surface facing it. None outside a spot cone
+    // or where the profile gives no light.
+    pub fn incident(&self, p: Point) -> Option<(Vector, f64, Color)> {
+        match self {
+            Light::Point {
+                position,
+                intensity,
+                profile,
+            } => {
+                let to_light = *position - p;
+                let distance = to_light.length();
+                let wi = to_light / distance;
+                let scale = profile.as_ref().map_or(1.0, |profile| profile.scale(-wi));
+                if scale <= 0.0 {
+                    return None;
+                }
+                Some((wi, distance, scale * *intensity / (distance * distance)))
+            }
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                inner,
+                outer,
+                profile,
+            } => {
+                let to_light = *position - p;
+                let distance = to_light.length();
+                let wi = to_light / distance;
+                let falloff = spot_falloff(Vector::dot(-wi, *direction), *inner, *outer)
+                    * profile.as_ref().map_or(1.0, |profile| profile.scale(-wi));
+                if falloff <= 0.0 {
+                    return None;
+                }
+                Some((wi, distance, falloff * *intensity / (distance * distance)))
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => Some((-*direction, f64::INFINITY, *irradiance)),
+        }
+    }
+}
+
+// IES profile placed in the scene.
+#[derive(Clone)]
+pub struct Photometry {
+    profile: Rc<IesProfile>,
+    nadir: Vector,
+    c0: Vector,
+    c90: Vector,
+    // Makes the profile average 1 over the sphere.
+    normalization: f64,
+}
+
+impl Photometry {
+    fn new(profile: Rc<IesProfile>, nadir: Vector, c0: Vector) -> Self {
+        let nadir = nadir.unit();
+        // Horizontal angles turn counterclockwise seen from above.
+        let c0 = (c0 - Vector::dot(c0, nadir) * nadir).unit();
+        let c90 = Vector::cross(c0, nadir);
+        let lumens = profile.lumens();
+        let normalization = if lumens > 0.0 { 4.0 * PI / lumens } else { 0.0 };
+        Photometry {
+            profile,
+            nadir,
+            c0,
+            c90,
+            normalization,
+        }
+    }
+
+    // Relative intensity along the unit direction `w` leaving the light.
+    fn scale(&self, w: Vector) -> f64 {
+        let vertical = Vector::dot(w, self.nadir).clamp(-1.0, 1.0).acos();
+        let horizontal = Vector::dot(w, self.c90).atan2(Vector::dot(w, self.c0));
+        self.normalization
+            * self
+                .profile
+                .candela(rtweeknd::rad2deg(vertical), rtweeknd::rad2deg(horizontal))
+    }
+}
+
+// Intensity in W/sr of a lamp spreading its flux over `solid_angle`.
+fn intensity(power: Power, solid_angle: f64) -> f64 {
+    match power {
+        Power::Watts(w) => w / solid_angle,
+        Power::Lumens(lm) => lm / LUMENS_PER_WATT / solid_angle,
+        Power::Candela(cd) => cd / LUMENS_PER_WATT,
+    }
+}
+
+fn normalized(color: Color) -> Color {
+    let l = color::luminance(color);
+    if l > 0.0 {
+        color / l
+    } else {
+        Color::zero()
+    }
+}
+
+// Smoothstep from the outer to the inner cone.
+fn spot_falloff(cos_theta: f64, inner: f64, outer: f64) -> f64 {
+    let cos_inner = rtweeknd::deg2rad(inner).cos();
+    let cos_outer = rtweeknd::deg2rad(outer).cos();
+    if cos_theta >= cos_inner {
+        return 1.0;
+    }
+    if cos_inner <= cos_outer {
+        return 0.0;
+    }
+    let t = ((cos_theta - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -1,19 +1,11 @@
-mod camera;
-mod color;
-mod hittable;
-mod interval;
-mod material;
-mod ray;
-mod rtweeknd;
-mod vector;
-
 use std::rc::Rc;
 
-use camera::Camera;
-use color::Color;
-use hittable::{HittableList, Sphere};
-use material::{Dielectric, Lambertian, Material, Metal};
-use vector::{Point, Vector};
+use tracer::camera::Camera;
+use tracer::color::Color;
+use tracer::hittable::{HittableList, Sphere};
+use tracer::material::{Dielectric, Lambertian, Material, Metal};
+use tracer::rtweeknd;
+use tracer::vector::{Point, Vector};
 fn main() {
     let mut world = HittableList::new();
 
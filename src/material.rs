@@ -1,8 +1,64 @@
+use crate::color;
+use crate::colorspace::ColorSpace;
+use crate::light;
+use crate::medium::Medium;
+use crate::microfacet::{self, TrowbridgeReitz};
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::vector::Vector;
-use crate::{color, rtweeknd};
+use crate::rtweeknd::PI;
+use crate::sampler::{self, Sampler};
+use crate::texture::{self, Texture};
+use crate::thinfilm::ThinFilm;
+use crate::vector::{Point, Vector};
 use crate::{color::Color, hittable::HitRecord};
 
+use std::rc::Rc;
+
+// Kind of scattering event, used to budget path depth per lobe.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Lobe {
+    Diffuse,
+    Glossy,
+    // Perfect mirror reflection. It counts as glossy, but unlike glossy
+    // reflection it has no part in eval.
+    Specular,
+    Transmission,
+}
+
+impl Lobe {
+    // Specular and glossy events are transmissions when they cross the
+    // surface and reflections otherwise.
+    pub fn specular(direction: Vector, normal: Vector) -> Self {
+        if Vector::dot(direction, normal) < 0.0 {
+            Lobe::Transmission
+        } else {
+            Lobe::Specular
+        }
+    }
+
+    pub fn glossy(direction: Vector, normal: Vector) -> Self {
+        if Vector::dot(direction, normal) < 0.0 {
+            Lobe::Transmission
+        } else {
+            Lobe::Glossy
+        }
+    }
+
+    // Reflection off a smooth or a rough surface.
+    pub fn reflection(smooth: bool) -> Self {
+        if smooth {
+            Lobe::Specular
+        } else {
+            Lobe::Glossy
+        }
+    }
+
+    // Whether eval covers the directions this lobe scatters to.
+    pub fn is_evaluated(self) -> bool {
+        matches!(self, Lobe::Diffuse | Lobe::Glossy)
+    }
+}
+
 pub trait Material {
     fn scatter(
         &self,
@@ -10,7 +66,42 @@ pub trait Material {
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
-    ) -> bool;
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe>;
+
+    fn emitted(&self, _u: f64, _v: f64, _p: Point) -> Color {
+        Color::zero()
+    }
+
+    // The medium enclosed by surfaces with this material, if any.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+
+    // Coverage in [0,1]. Rays pass straight through the uncovered fraction.
+    fn alpha(&self, _rec: &HitRecord) -> f64 {
+        1.0
+    }
+
+    // Overall reflectance color, used as a guide by the denoiser. Clear
+    // materials are white.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    // Fraction of light arriving from unit direction `wi` that scatters
+    // along the reversed ray, the BSDF times the cosine. Used to light the
+    // surface with analytic lights, which materials without it, such as
+    // perfect mirrors and glass, do not receive.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vector) -> Color {
+        Color::zero()
+    }
+
+    // Whether light gets through the surface by refraction, so that it
+    // still reaches points it casts a shadow on.
+    fn transmits(&self, _rec: &HitRecord) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
@@ -26,18 +117,27 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter(
         &self,
-        r_in: &Ray,
+        _r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = rec.normal + Vector::random_unit_vector();
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        let mut scatter_direction = rec.normal + sampler::unit_vector(sampler.get_2d());
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
         *scattered = Ray::new(rec.p, scatter_direction);
         *attenuation = self.albedo;
-        true
+        Some(Lobe::Diffuse)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        Vector::dot(rec.normal, wi).max(0.0) / PI * self.albedo
     }
 }
 
@@ -62,22 +162,152 @@ impl Material for Metal {
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
-    ) -> bool {
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
         let mut reflected = Vector::reflect(r_in.direction(), rec.normal);
-        reflected = reflected.unit() + self.fuzz * Vector::random_unit_vector();
+        reflected = reflected.unit() + self.fuzz * sampler::unit_vector(sampler.get_2d());
         *scattered = Ray::new(rec.p, reflected);
         *attenuation = self.albedo;
-        Vector::dot(scattered.direction(), rec.normal) > 0.0
+        (Vector::dot(scattered.direction(), rec.normal) > 0.0)
+            .then_some(Lobe::reflection(self.fuzz <= 0.0))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    // Scatter offsets the mirror direction r by a uniform point on a sphere
+    // of radius fuzz, so BSDF * cos is the albedo times the density of the
+    // directions through that sphere: both intersections of the direction
+    // with it, each weighted by t^2 / (4 pi fuzz^2 |cos|).
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        if self.fuzz <= 0.0 || Vector::dot(wi, rec.normal) <= 0.0 {
+            return Color::zero();
+        }
+        let r = Vector::reflect(r_in.direction(), rec.normal).unit();
+        let b = Vector::dot(wi, r);
+        let c = 1.0 - self.fuzz * self.fuzz;
+        let discriminant = b * b - c;
+        if b <= 0.0 || discriminant <= 0.0 {
+            return Color::zero();
+        }
+        let pdf = (4.0 * b * b - 2.0 * c) / (4.0 * PI * self.fuzz * discriminant.sqrt());
+        pdf * self.albedo
+    }
+}
+
+// Wavelength dependent index of refraction, wavelengths in micrometers.
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    // n = a + b / lambda^2
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    // Dense flint glass, strongly dispersive.
+    pub fn sf11() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+
+    // Cauchy fit through the index at the d-line and the Abbe number
+    // (n_d - 1) / (n_F - n_C), as listed in glass catalogs.
+    pub fn from_abbe(n_d: f64, abbe: f64) -> Self {
+        let (d, f, c) = (0.5876f64, 0.4861f64, 0.6563f64);
+        let b = (n_d - 1.0) / abbe / (1.0 / (f * f) - 1.0 / (c * c));
+        Dispersion::Cauchy {
+            a: n_d - b / (d * d),
+            b,
+        }
+    }
+
+    pub fn refraction_index(&self, lambda_nm: f64) -> f64 {
+        let l = lambda_nm / 1000.0;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
     }
 }
 
 pub struct Dielectric {
     refraction_index: f64,
+    dispersion: Option<Dispersion>,
+    // Beer-Lambert absorption coefficient per unit length inside the medium.
+    absorption: Color,
+    priority: u32,
+    thin_film: Option<ThinFilm>,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
-        Dielectric { refraction_index }
+        Dielectric {
+            refraction_index,
+            dispersion: None,
+            absorption: Color::zero(),
+            priority: 0,
+            thin_film: None,
+        }
+    }
+
+    // Without a wavelength on the ray the index at the sodium d-line is used.
+    pub fn dispersive(dispersion: Dispersion) -> Self {
+        Dielectric {
+            refraction_index: dispersion.refraction_index(587.6),
+            dispersion: Some(dispersion),
+            absorption: Color::zero(),
+            priority: 0,
+            thin_film: None,
+        }
+    }
+
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    // Where dielectrics overlap, e.g. liquid modelled slightly larger than
+    // the inside of its glass, the higher priority one takes precedence.
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.thin_film = Some(film);
+        self
+    }
+
+    // Absorption chosen so that light keeps `transmittance` of its energy
+    // after travelling `distance` through the medium.
+    pub fn with_transmittance(self, transmittance: Color, distance: f64) -> Self {
+        let mut absorption = Color::zero();
+        for c in 0..3 {
+            absorption[c] = -transmittance[c].max(1e-9).ln() / distance;
+        }
+        self.with_absorption(absorption)
+    }
+
+    fn refraction_index_at(&self, wavelength: Option<f64>) -> f64 {
+        match (self.dispersion, wavelength) {
+            (Some(dispersion), Some(lambda)) => dispersion.refraction_index(lambda),
+            _ => self.refraction_index,
+        }
     }
 
     fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
@@ -94,25 +324,1168 @@ impl Material for Dielectric {
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
-    ) -> bool {
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        // Absorption inside is applied by the camera through medium().
         *attenuation = color::Color::new(1.0, 1.0, 1.0);
+        let refraction_index = self.refraction_index_at(r_in.wavelength());
         let ri = if rec.front_face {
-            1.0 / self.refraction_index
+            rec.exterior_index / refraction_index
         } else {
-            self.refraction_index
+            refraction_index / rec.exterior_index
         };
         let unit_direction = r_in.direction().unit();
 
         let cos_theta = Vector::dot(-unit_direction, rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_refract = ri * sin_theta > 1.0;
-        let mut direction = Vector::zero();
-        if cannot_refract || Self::reflectance(cos_theta, ri) > rtweeknd::random() {
-            direction = Vector::reflect(unit_direction, rec.normal);
+        let direction = if cannot_refract {
+            Vector::reflect(unit_direction, rec.normal)
+        } else if let Some(film) = &self.thin_film {
+            // The film reflects each channel differently, so pick a side by
+            // the average and reweight.
+            let (n_incident, n_substrate) = if rec.front_face {
+                (rec.exterior_index, refraction_index)
+            } else {
+                (refraction_index, rec.exterior_index)
+            };
+            let r = film.reflectance(
+                rec,
+                cos_theta,
+                n_incident,
+                Color::new(n_substrate, n_substrate, n_substrate),
+                Color::zero(),
+                r_in.wavelength(),
+            );
+            let p = ((r.x() + r.y() + r.z()) / 3.0).clamp(1e-4, 1.0 - 1e-4);
+            if sampler.get_1d() < p {
+                *attenuation = r / p;
+                Vector::reflect(unit_direction, rec.normal)
+            } else {
+                *attenuation = (Color::new(1.0, 1.0, 1.0) - r) / (1.0 - p);
+                Vector::refract(&unit_direction, &rec.normal, ri)
+            }
+        } else if Self::reflectance(cos_theta, ri) > sampler.get_1d() {
+            Vector::reflect(unit_direction, rec.normal)
         } else {
-            direction = Vector::refract(&unit_direction, &rec.normal, ri);
-        }
+            Vector::refract(&unit_direction, &rec.normal, ri)
+        };
         *scattered = Ray::new(rec.p, direction);
+        Some(Lobe::specular(direction, rec.normal))
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(Medium::absorbing(
+            self.refraction_index,
+            self.priority,
+            self.absorption,
+        ))
+    }
+
+    fn transmits(&self, _rec: &HitRecord) -> bool {
+        true
+    }
+}
+
+// GGX microfacet conductor with a complex index of refraction per channel.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: TrowbridgeReitz,
+    thin_film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            thin_film: None,
+        }
+    }
+
+    pub fn anisotropic(eta: Color, k: Color, roughness_u: f64, roughness_v: f64) -> Self {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(
+                roughness_u.clamp(0.0, 1.0).powi(2),
+                roughness_v.clamp(0.0, 1.0).powi(2),
+            ),
+            thin_film: None,
+        }
+    }
+
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.thin_film = Some(film);
+        self
+    }
+
+    fn fresnel(&self, r_in: &Ray, rec: &HitRecord, cos_i: f64) -> Color {
+        let outer = rec.exterior_index;
+        match &self.thin_film {
+            Some(film) => film.reflectance(rec, cos_i, outer, self.eta, self.k, r_in.wavelength()),
+            None => microfacet::fresnel_conductor(cos_i, self.eta / outer, self.k / outer),
+        }
+    }
+
+    // Complex IOR presets sampled at red, green and blue wavelengths.
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.155, 0.117, 0.138),
+            Color::new(4.828, 3.122, 2.147),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        if self.distribution.is_smooth() {
+            let wi = Vector::new(-wo.x(), -wo.y(), wo.z());
+            *scattered = Ray::new(rec.p, frame.transform(wi));
+            *attenuation = self.fresnel(r_in, rec, wo.z());
+            return Some(Lobe::Specular);
+        }
+
+        let wm = self
+            .distribution
+            .sample_visible_normal(wo, sampler.get_2d());
+        let wi = microfacet::reflect(wo, wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        // With visible normal sampling the weight reduces to F * G2 / G1.
+        let f = self.fresnel(r_in, rec, Vector::dot(wo, wm));
+        *scattered = Ray::new(rec.p, frame.transform(wi));
+        *attenuation = f * (self.distribution.g(wo, wi) / self.distribution.g1(wo));
+        Some(Lobe::Glossy)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        microfacet::fresnel_conductor(1.0, self.eta, self.k)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        let wi = frame.to_local(wi);
+        let Some(wm) = half_vector(wo, wi) else {
+            return Color::zero();
+        };
+        self.fresnel(r_in, rec, Vector::dot(wo, wm)) * glossy(&self.distribution, wo, wi, wm)
+    }
+}
+
+// GGX microfacet glass with rough reflection and transmission.
+pub struct RoughDielectric {
+    refraction_index: f64,
+    distribution: TrowbridgeReitz,
+    priority: u32,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64) -> Self {
+        RoughDielectric {
+            refraction_index,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            priority: 0,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let eta = if rec.front_face {
+            self.refraction_index / rec.exterior_index
+        } else {
+            rec.exterior_index / self.refraction_index
+        };
+
+        let wm = if self.distribution.is_smooth() {
+            Vector::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution
+                .sample_visible_normal(wo, sampler.get_2d())
+        };
+        let f = microfacet::fresnel_dielectric(Vector::dot(wo, wm), eta);
+
+        // Pick reflection or transmission with probability F, so the Fresnel
+        // term cancels out of the weight.
+        let wi = if sampler.get_1d() < f {
+            let wi = microfacet::reflect(wo, wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            match microfacet::refract(wo, wm, eta) {
+                Some(wi) if wi.z() < 0.0 => wi,
+                _ => return None,
+            }
+        };
+
+        *attenuation = if self.distribution.is_smooth() {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            let weight = self.distribution.g(wo, wi) / self.distribution.g1(wo);
+            Color::new(weight, weight, weight)
+        };
+        *scattered = Ray::new(rec.p, frame.transform(wi));
+        if self.distribution.is_smooth() {
+            Some(Lobe::specular(scattered.direction(), rec.normal))
+        } else {
+            Some(Lobe::glossy(scattered.direction(), rec.normal))
+        }
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(Medium::absorbing(
+            self.refraction_index,
+            self.priority,
+            Color::zero(),
+        ))
+    }
+
+    fn transmits(&self, _rec: &HitRecord) -> bool {
         true
     }
+
+    // Reflection only, lights are not seen through the surface.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        let wi = frame.to_local(wi);
+        let Some(wm) = half_vector(wo, wi) else {
+            return Color::zero();
+        };
+        let eta = if rec.front_face {
+            self.refraction_index / rec.exterior_index
+        } else {
+            rec.exterior_index / self.refraction_index
+        };
+        let f = microfacet::fresnel_dielectric(Vector::dot(wo, wm), eta);
+        let reflection = f * glossy(&self.distribution, wo, wi, wm);
+        Color::new(reflection, reflection, reflection)
+    }
+}
+
+// Principled uber material in the spirit of the Disney BSDF. Every lobe is
+// sampled stochastically, so each scatter picks one of clearcoat, metal,
+// transmission, dielectric specular or diffuse. The parameters follow the
+// usual metallic/roughness naming so they map directly onto glTF and MTL
+// material definitions.
+pub struct Principled {
+    pub base_color: Rc<dyn Texture>,
+    pub metallic: Rc<dyn Texture>,
+    pub roughness: Rc<dyn Texture>,
+    // Dielectric specular amount, 0.5 corresponds to a reflectance of 4%.
+    pub specular: Rc<dyn Texture>,
+    pub sheen: Rc<dyn Texture>,
+    pub sheen_tint: Rc<dyn Texture>,
+    pub clearcoat: Rc<dyn Texture>,
+    pub clearcoat_roughness: Rc<dyn Texture>,
+    pub transmission: Rc<dyn Texture>,
+    pub refraction_index: f64,
+    pub emission: Rc<dyn Texture>,
+    pub emission_strength: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Rc<dyn Texture>) -> Self {
+        Principled {
+            base_color,
+            metallic: texture::constant(0.0),
+            roughness: texture::constant(0.5),
+            specular: texture::constant(0.5),
+            sheen: texture::constant(0.0),
+            sheen_tint: texture::constant(0.5),
+            clearcoat: texture::constant(0.0),
+            clearcoat_roughness: texture::constant(0.03),
+            transmission: texture::constant(0.0),
+            refraction_index: 1.5,
+            emission: texture::solid(Color::zero()),
+            emission_strength: 1.0,
+        }
+    }
+
+    pub fn from_color(base_color: Color) -> Self {
+        Self::new(texture::solid(base_color))
+    }
+
+    // Samples a GGX reflection off a microfacet and returns the local
+    // direction along with the G2 / G1 weight.
+    fn sample_reflection(
+        distribution: &TrowbridgeReitz,
+        wo: Vector,
+        u: (f64, f64),
+    ) -> Option<(Vector, Vector, f64)> {
+        let wm = if distribution.is_smooth() {
+            Vector::new(0.0, 0.0, 1.0)
+        } else {
+            distribution.sample_visible_normal(wo, u)
+        };
+        let wi = microfacet::reflect(wo, wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        let weight = if distribution.is_smooth() {
+            1.0
+        } else {
+            distribution.g(wo, wi) / distribution.g1(wo)
+        };
+        Some((wi, wm, weight))
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        let (u, v, p) = (rec.u, rec.v, rec.p);
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = self.metallic.scalar(u, v, p).clamp(0.0, 1.0);
+        let roughness = self.roughness.scalar(u, v, p);
+        let transmission = self.transmission.scalar(u, v, p).clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.scalar(u, v, p).clamp(0.0, 1.0);
+
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let distribution = TrowbridgeReitz::from_roughness(roughness);
+
+        // Clearcoat is a colorless IOR 1.5 layer on top of everything else.
+        if clearcoat > 0.0
+            && sampler.get_1d() < clearcoat * microfacet::fresnel_dielectric(wo.z(), 1.5)
+        {
+            let coat = TrowbridgeReitz::from_roughness(self.clearcoat_roughness.scalar(u, v, p));
+            let (wi, _, weight) = Self::sample_reflection(&coat, wo, sampler.get_2d())?;
+            *scattered = Ray::new(rec.p, frame.transform(wi));
+            *attenuation = Color::new(weight, weight, weight);
+            return Some(Lobe::reflection(coat.is_smooth()));
+        }
+
+        let (wi, weight, lobe) = if sampler.get_1d() < metallic {
+            // Metal: colored Schlick Fresnel with the base color as F0.
+            let (wi, wm, weight) = Self::sample_reflection(&distribution, wo, sampler.get_2d())?;
+            let f = schlick(base_color, Vector::dot(wo, wm));
+            (wi, f * weight, Lobe::reflection(distribution.is_smooth()))
+        } else if sampler.get_1d() < transmission {
+            // Rough glass tinted by the base color on transmission.
+            let eta = if rec.front_face {
+                self.refraction_index / rec.exterior_index
+            } else {
+                rec.exterior_index / self.refraction_index
+            };
+            let wm = if distribution.is_smooth() {
+                Vector::new(0.0, 0.0, 1.0)
+            } else {
+                distribution.sample_visible_normal(wo, sampler.get_2d())
+            };
+            let f = microfacet::fresnel_dielectric(Vector::dot(wo, wm), eta);
+            let (wi, tint) = if sampler.get_1d() < f {
+                (microfacet::reflect(wo, wm), Color::new(1.0, 1.0, 1.0))
+            } else {
+                match microfacet::refract(wo, wm, eta) {
+                    Some(wi) => (wi, base_color),
+                    None => return None,
+                }
+            };
+            if wi.z() == 0.0 || (wi.z() > 0.0) != (Vector::dot(wi, wm) > 0.0) {
+                return None;
+            }
+            let weight = if distribution.is_smooth() {
+                1.0
+            } else {
+                distribution.g(wo, wi) / distribution.g1(wo)
+            };
+            let lobe = if wi.z() < 0.0 {
+                Lobe::Transmission
+            } else {
+                Lobe::reflection(distribution.is_smooth())
+            };
+            (wi, tint * weight, lobe)
+        } else {
+            // Dielectric specular over a diffuse base.
+            let specular = self.specular.scalar(u, v, p).clamp(0.0, 1.0);
+            let f0 = (0.08 * specular).sqrt().min(0.999);
+            let spec_eta = (1.0 + f0) / (1.0 - f0);
+            if sampler.get_1d() < microfacet::fresnel_dielectric(wo.z(), spec_eta) {
+                let (wi, _, weight) = Self::sample_reflection(&distribution, wo, sampler.get_2d())?;
+                let lobe = Lobe::reflection(distribution.is_smooth());
+                (wi, Color::new(weight, weight, weight), lobe)
+            } else {
+                let wi = sampler::cosine_direction(sampler.get_2d());
+                let wh = (wi + wo).unit();
+                let sheen = self.sheen.scalar(u, v, p);
+                let sheen_tint = self.sheen_tint.scalar(u, v, p);
+                let lum = color::luminance(base_color);
+                let tint = if lum > 0.0 {
+                    base_color / lum
+                } else {
+                    Color::new(1.0, 1.0, 1.0)
+                };
+                let sheen_color =
+                    (1.0 - sheen_tint) * Color::new(1.0, 1.0, 1.0) + sheen_tint * tint;
+                let sheen_term = sheen * (1.0 - Vector::dot(wi, wh)).clamp(0.0, 1.0).powi(5);
+                (wi, base_color + sheen_term * sheen_color, Lobe::Diffuse)
+            }
+        };
+
+        *scattered = Ray::new(rec.p, frame.transform(wi));
+        *attenuation = weight;
+        Some(lobe)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
+        self.emission_strength * self.emission.value(u, v, p)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color.value(rec.u, rec.v, rec.p)
+    }
+
+    fn transmits(&self, rec: &HitRecord) -> bool {
+        self.transmission.scalar(rec.u, rec.v, rec.p) > 0.0
+    }
+
+    // The lobes weighted by the probabilities scatter picks them with.
+    // Transmission is left out, as analytic lights cannot be seen through
+    // the surface.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        let (u, v, p) = (rec.u, rec.v, rec.p);
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        let wi = frame.to_local(wi);
+        let Some(wm) = half_vector(wo, wi) else {
+            return Color::zero();
+        };
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = self.metallic.scalar(u, v, p).clamp(0.0, 1.0);
+        let transmission = self.transmission.scalar(u, v, p).clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.scalar(u, v, p).clamp(0.0, 1.0);
+        let distribution = TrowbridgeReitz::from_roughness(self.roughness.scalar(u, v, p));
+        let specular_lobe = glossy(&distribution, wo, wi, wm);
+
+        let coat_probability = clearcoat * microfacet::fresnel_dielectric(wo.z(), 1.5);
+        let coat = TrowbridgeReitz::from_roughness(self.clearcoat_roughness.scalar(u, v, p));
+        let coat_lobe = glossy(&coat, wo, wi, wm);
+
+        let metal = schlick(base_color, Vector::dot(wo, wm)) * specular_lobe;
+
+        let eta = if rec.front_face {
+            self.refraction_index / rec.exterior_index
+        } else {
+            rec.exterior_index / self.refraction_index
+        };
+        let glass = microfacet::fresnel_dielectric(Vector::dot(wo, wm), eta) * specular_lobe;
+
+        let specular = self.specular.scalar(u, v, p).clamp(0.0, 1.0);
+        let f0 = (0.08 * specular).sqrt().min(0.999);
+        let spec_fresnel = microfacet::fresnel_dielectric(wo.z(), (1.0 + f0) / (1.0 - f0));
+        let sheen = self.sheen.scalar(u, v, p);
+        let sheen_tint = self.sheen_tint.scalar(u, v, p);
+        let lum = color::luminance(base_color);
+        let tint = if lum > 0.0 {
+            base_color / lum
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        };
+        let sheen_color = (1.0 - sheen_tint) * Color::new(1.0, 1.0, 1.0) + sheen_tint * tint;
+        let sheen_term = sheen * (1.0 - Vector::dot(wi, wm)).clamp(0.0, 1.0).powi(5);
+        let diffuse = wi.z() / PI * (base_color + sheen_term * sheen_color);
+        let dielectric = spec_fresnel * specular_lobe * Color::new(1.0, 1.0, 1.0)
+            + (1.0 - spec_fresnel) * diffuse;
+
+        let base = metallic * metal
+            + (1.0 - metallic)
+                * (transmission * glass * Color::new(1.0, 1.0, 1.0)
+                    + (1.0 - transmission) * dielectric);
+        coat_probability * coat_lobe * Color::new(1.0, 1.0, 1.0) + (1.0 - coat_probability) * base
+    }
+}
+
+// Normalized half vector of directions on the outside of the local frame.
+fn half_vector(wo: Vector, wi: Vector) -> Option<Vector> {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return None;
+    }
+    Some((wo + wi).unit())
+}
+
+// Microfacet reflection D * G2 / (4 cos_o), the BRDF times the cosine
+// without Fresnel. Smooth surfaces only reflect the mirror direction.
+fn glossy(distribution: &TrowbridgeReitz, wo: Vector, wi: Vector, wm: Vector) -> f64 {
+    if distribution.is_smooth() {
+        return 0.0;
+    }
+    distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z())
+}
+
+fn schlick(f0: Color, cosine: f64) -> Color {
+    let m = (1.0 - cosine).clamp(0.0, 1.0).powi(5);
+    f0 + (Color::new(1.0, 1.0, 1.0) - f0) * m
+}
+
+// Tangent-space normal map applied on top of another material. The map
+// stores normals remapped from [-1,1] to [0,1] with +z along the surface
+// normal, as produced by common baking tools.
+pub struct NormalMap {
+    inner: Rc<dyn Material>,
+    map: Rc<dyn Texture>,
+    strength: f64,
+}
+
+impl NormalMap {
+    pub fn new(inner: Rc<dyn Material>, map: Rc<dyn Texture>) -> Self {
+        NormalMap {
+            inner,
+            map,
+            strength: 1.0,
+        }
+    }
+
+    pub fn with_strength(mut self, strength: f64) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let n = rec.outward_normal();
+        let tangent = rec.tangent - Vector::dot(rec.tangent, n) * n;
+        if tangent.near_zero() {
+            return rec.clone();
+        }
+        let tangent = tangent.unit();
+        let mut bitangent = Vector::cross(n, tangent);
+        if Vector::dot(bitangent, rec.bitangent) < 0.0 {
+            bitangent = -bitangent;
+        }
+
+        let m = 2.0 * self.map.value(rec.u, rec.v, rec.p) - Vector::new(1.0, 1.0, 1.0);
+        let local = Vector::new(
+            self.strength * m.x(),
+            self.strength * m.y(),
+            m.z().max(1e-3),
+        );
+        let mapped = (local.x() * tangent + local.y() * bitangent + local.z() * n).unit();
+        let mut perturbed = rec.clone();
+        perturbed.normal = if rec.front_face { mapped } else { -mapped };
+        perturbed
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        self.inner
+            .scatter(r_in, &self.perturb(rec), attenuation, scattered, sampler)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
+        self.inner.emitted(u, v, p)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.inner.medium()
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f64 {
+        self.inner.alpha(rec)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.inner.albedo(rec)
+    }
+
+    fn transmits(&self, rec: &HitRecord) -> bool {
+        self.inner.transmits(&self.perturb(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        self.inner.eval(r_in, &self.perturb(rec), wi)
+    }
+}
+
+// Scalar height map applied on top of another material. The height is
+// differentiated in uv space and the normal tilted accordingly.
+pub struct BumpMap {
+    inner: Rc<dyn Material>,
+    height: Rc<dyn Texture>,
+    strength: f64,
+}
+
+impl BumpMap {
+    pub fn new(inner: Rc<dyn Material>, height: Rc<dyn Texture>, strength: f64) -> Self {
+        BumpMap {
+            inner,
+            height,
+            strength,
+        }
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        const DELTA: f64 = 1.0 / 1024.0;
+        let n = rec.outward_normal();
+        let h = self.height.scalar(rec.u, rec.v, rec.p);
+        let h_u = self
+            .height
+            .scalar(rec.u + DELTA, rec.v, rec.p + DELTA * rec.tangent);
+        let h_v = self
+            .height
+            .scalar(rec.u, rec.v + DELTA, rec.p + DELTA * rec.bitangent);
+        let dhdu = self.strength * (h_u - h) / DELTA;
+        let dhdv = self.strength * (h_v - h) / DELTA;
+
+        let dpdu = rec.tangent + dhdu * n;
+        let dpdv = rec.bitangent + dhdv * n;
+        let mut bumped = Vector::cross(dpdu, dpdv);
+        if bumped.near_zero() {
+            return rec.clone();
+        }
+        bumped = bumped.unit();
+        if Vector::dot(bumped, n) < 0.0 {
+            bumped = -bumped;
+        }
+        let mut perturbed = rec.clone();
+        perturbed.normal = if rec.front_face { bumped } else { -bumped };
+        perturbed
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        self.inner
+            .scatter(r_in, &self.perturb(rec), attenuation, scattered, sampler)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
+        self.inner.emitted(u, v, p)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.inner.medium()
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f64 {
+        self.inner.alpha(rec)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.inner.albedo(rec)
+    }
+
+    fn transmits(&self, rec: &HitRecord) -> bool {
+        self.inner.transmits(&self.perturb(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        self.inner.eval(r_in, &self.perturb(rec), wi)
+    }
+}
+
+// Clear dielectric coat layered over any other material, e.g. car paint or
+// varnished wood. Light either reflects off the coat or passes through it,
+// picking up the coat tint, and interacts with the base.
+pub struct Coated {
+    base: Rc<dyn Material>,
+    refraction_index: f64,
+    distribution: TrowbridgeReitz,
+    // Transmittance of a single pass through the coat at normal incidence.
+    tint: Color,
+    thin_film: Option<ThinFilm>,
+}
+
+impl Coated {
+    pub fn new(base: Rc<dyn Material>, refraction_index: f64, roughness: f64) -> Self {
+        Coated {
+            base,
+            refraction_index,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            tint: Color::new(1.0, 1.0, 1.0),
+            thin_film: None,
+        }
+    }
+
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.thin_film = Some(film);
+        self
+    }
+
+    fn reflectance(&self, r_in: &Ray, rec: &HitRecord, cos_i: f64) -> Color {
+        let eta = self.refraction_index / rec.exterior_index;
+        match &self.thin_film {
+            Some(film) => {
+                let n = Color::new(
+                    self.refraction_index,
+                    self.refraction_index,
+                    self.refraction_index,
+                );
+                film.reflectance(
+                    rec,
+                    cos_i,
+                    rec.exterior_index,
+                    n,
+                    Color::zero(),
+                    r_in.wavelength(),
+                )
+            }
+            None => {
+                let f = microfacet::fresnel_dielectric(cos_i, eta);
+                Color::new(f, f, f)
+            }
+        }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        if !rec.front_face || wo.z() <= 0.0 {
+            return self
+                .base
+                .scatter(r_in, rec, attenuation, scattered, sampler);
+        }
+
+        let wm = if self.distribution.is_smooth() {
+            Vector::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution
+                .sample_visible_normal(wo, sampler.get_2d())
+        };
+        let r = self.reflectance(r_in, rec, Vector::dot(wo, wm));
+        let p = ((r.x() + r.y() + r.z()) / 3.0).clamp(1e-4, 1.0 - 1e-4);
+
+        if sampler.get_1d() < p {
+            let wi = microfacet::reflect(wo, wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let g = if self.distribution.is_smooth() {
+                1.0
+            } else {
+                self.distribution.g(wo, wi) / self.distribution.g1(wo)
+            };
+            *scattered = Ray::new(rec.p, frame.transform(wi));
+            *attenuation = g * r / p;
+            return Some(Lobe::reflection(self.distribution.is_smooth()));
+        }
+
+        let lobe = self
+            .base
+            .scatter(r_in, rec, attenuation, scattered, sampler)?;
+        let cos_o = Vector::dot(scattered.direction().unit(), rec.normal);
+        let mut layer = (Color::new(1.0, 1.0, 1.0) - r) / (1.0 - p);
+        if cos_o > 0.0 {
+            // Light leaving the base has to get out through the coat again.
+            let f_out =
+                microfacet::fresnel_dielectric(cos_o, self.refraction_index / rec.exterior_index);
+            layer *= 1.0 - f_out;
+            let path = 1.0 / wo.z().max(1e-3) + 1.0 / cos_o.max(1e-3);
+            for c in 0..3 {
+                layer[c] *= self.tint[c].max(0.0).powf(path);
+            }
+        }
+        *attenuation = *attenuation * layer;
+        Some(lobe)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
+        self.base.emitted(u, v, p)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f64 {
+        self.base.alpha(rec)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base.albedo(rec)
+    }
+
+    fn transmits(&self, rec: &HitRecord) -> bool {
+        self.base.transmits(rec)
+    }
+
+    // Reflection off the coat plus the base's, which light reaches through
+    // the coat and leaves through it again, as in scatter.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit());
+        if !rec.front_face || wo.z() <= 0.0 {
+            return self.base.eval(r_in, rec, wi);
+        }
+        let wi_local = frame.to_local(wi);
+        let Some(wm) = half_vector(wo, wi_local) else {
+            return Color::zero();
+        };
+        let coat = self.reflectance(r_in, rec, Vector::dot(wo, wm))
+            * glossy(&self.distribution, wo, wi_local, wm);
+
+        let f_out = microfacet::fresnel_dielectric(
+            wi_local.z(),
+            self.refraction_index / rec.exterior_index,
+        );
+        let mut layer =
+            (Color::new(1.0, 1.0, 1.0) - self.reflectance(r_in, rec, wo.z())) * (1.0 - f_out);
+        let path = 1.0 / wo.z().max(1e-3) + 1.0 / wi_local.z().max(1e-3);
+        for c in 0..3 {
+            layer[c] *= self.tint[c].max(0.0).powf(path);
+        }
+        coat + layer * self.base.eval(r_in, rec, wi)
+    }
+}
+
+// Random walk subsurface scattering. The surface is a smooth dielectric
+// boundary and the interior a scattering medium that the camera walks
+// through, so light can exit away from where it entered.
+pub struct Subsurface {
+    interface: Dielectric,
+    medium: Medium,
+    albedo: Color,
+}
+
+impl Subsurface {
+    // `albedo` is the overall color after multiple scattering and
+    // `mean_free_path` the average distance light travels per channel.
+    pub fn new(albedo: Color, mean_free_path: Color, refraction_index: f64) -> Self {
+        let mut absorption = Color::zero();
+        let mut scattering = Color::zero();
+        for c in 0..3 {
+            let sigma_t = 1.0 / mean_free_path[c].max(1e-6);
+            let single = Self::single_scattering_albedo(albedo[c].clamp(0.0, 0.999));
+            scattering[c] = single * sigma_t;
+            absorption[c] = sigma_t - scattering[c];
+        }
+        Subsurface {
+            interface: Dielectric::new(refraction_index),
+            medium: Medium {
+                refraction_index,
+                priority: 0,
+                absorption,
+                scattering,
+                anisotropy: 0.0,
+            },
+            albedo,
+        }
+    }
+
+    // Forward scattering, as in skin, is around 0.8.
+    pub fn with_anisotropy(mut self, anisotropy: f64) -> Self {
+        self.medium.anisotropy = anisotropy.clamp(-0.99, 0.99);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.medium.priority = priority;
+        self.interface = self.interface.with_priority(priority);
+        self
+    }
+
+    // Inverts the multiple scattering albedo of a random walk to the single
+    // scattering albedo of the medium (Chiang et al., "Practical and
+    // Controllable Subsurface Scattering for Production Path Tracing", 2016).
+    fn single_scattering_albedo(albedo: f64) -> f64 {
+        let s = 4.09712 + 4.20863 * albedo
+            - (9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo).sqrt();
+        1.0 - s * s
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        self.interface
+            .scatter(r_in, rec, attenuation, scattered, sampler)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn transmits(&self, _rec: &HitRecord) -> bool {
+        true
+    }
+
+    // The interface is smooth, so analytic lights cannot reach the random
+    // walk. They light the surface as a diffuse reflector of the overall
+    // albedo instead, behind the Fresnel transmittance in and out.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        let cos_o = Vector::dot(-r_in.direction().unit(), rec.normal);
+        let cos_i = Vector::dot(wi, rec.normal);
+        if !rec.front_face || cos_o <= 0.0 || cos_i <= 0.0 {
+            return Color::zero();
+        }
+        let eta = self.medium.refraction_index / rec.exterior_index;
+        let transmittance = (1.0 - microfacet::fresnel_dielectric(cos_o, eta))
+            * (1.0 - microfacet::fresnel_dielectric(cos_i, eta));
+        transmittance * cos_i / PI * self.albedo
+    }
+}
+
+// Stochastic blend of two materials, e.g. rust over metal. The weight is the
+// probability of picking `b` and may be driven by a texture.
+pub struct Mix {
+    a: Rc<dyn Material>,
+    b: Rc<dyn Material>,
+    weight: Rc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(a: Rc<dyn Material>, b: Rc<dyn Material>, weight: Rc<dyn Texture>) -> Self {
+        Mix { a, b, weight }
+    }
+
+    fn weight(&self, u: f64, v: f64, p: Point) -> f64 {
+        self.weight.scalar(u, v, p).clamp(0.0, 1.0)
+    }
+
+    // Material the hit is shaded with. Derived from the hit point so that
+    // the alpha test, scattering and light evaluation all use the same one.
+    fn pick(&self, rec: &HitRecord) -> &dyn Material {
+        if sampler::hash_point(rec.p) < self.weight(rec.u, rec.v, rec.p) {
+            &*self.b
+        } else {
+            &*self.a
+        }
+    }
+}
+
+impl Material for Mix {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        self.pick(rec)
+            .scatter(r_in, rec, attenuation, scattered, sampler)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
+        let w = self.weight(u, v, p);
+        (1.0 - w) * self.a.emitted(u, v, p) + w * self.b.emitted(u, v, p)
+    }
+
+    // Media cannot vary over a surface, so a mix never encloses one.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f64 {
+        self.pick(rec).alpha(rec)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        let w = self.weight(rec.u, rec.v, rec.p);
+        (1.0 - w) * self.a.albedo(rec) + w * self.b.albedo(rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        self.pick(rec).eval(r_in, rec, wi)
+    }
+
+    fn transmits(&self, rec: &HitRecord) -> bool {
+        self.pick(rec).transmits(rec)
+    }
+}
+
+// Alpha mask over another material for leaves, fences and similar cutouts.
+pub struct Cutout {
+    inner: Rc<dyn Material>,
+    alpha: Rc<dyn Texture>,
+}
+
+impl Cutout {
+    pub fn new(inner: Rc<dyn Material>, alpha: Rc<dyn Texture>) -> Self {
+        Cutout { inner, alpha }
+    }
+}
+
+impl Material for Cutout {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        self.inner
+            .scatter(r_in, rec, attenuation, scattered, sampler)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
+        self.inner.emitted(u, v, p)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.inner.medium()
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f64 {
+        self.alpha.scalar(rec.u, rec.v, rec.p).clamp(0.0, 1.0) * self.inner.alpha(rec)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.inner.albedo(rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vector) -> Color {
+        self.inner.eval(r_in, rec, wi)
+    }
+
+    fn transmits(&self, rec: &HitRecord) -> bool {
+        self.inner.transmits(rec)
+    }
+}
+
+// Surface that only emits light, e.g. a light panel or a lamp shade. The
+// color, given in the working space `space`, is scaled to luminance 1 and
+// `radiance` gives the brightness in W/(sr m^2), see light::nits and
+// light::surface_radiance for other units.
+pub struct DiffuseLight {
+    emit: Rc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Rc<dyn Texture>) -> Self {
+        DiffuseLight { emit }
+    }
+
+    pub fn from_color(color: Color, radiance: f64, space: ColorSpace) -> Self {
+        let l = space.luminance(color);
+        let emit = if l > 0.0 {
+            color * (radiance / l)
+        } else {
+            Color::zero()
+        };
+        DiffuseLight::new(texture::solid(emit))
+    }
+
+    // Light of a black body, e.g. 2700K for an incandescent bulb.
+    pub fn blackbody(kelvin: f64, radiance: f64, space: ColorSpace) -> Self {
+        let color = space.convert_srgb(light::blackbody(kelvin));
+        DiffuseLight::from_color(color, radiance, space)
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _attenuation: &mut Color,
+        _scattered: &mut Ray,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        None
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
+        self.emit.value(u, v, p)
+    }
 }
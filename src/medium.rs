@@ -0,0 +1,153 @@
+use crate::color::Color;
+use crate::onb::Onb;
+use crate::rtweeknd::PI;
+use crate::spectrum;
+use crate::vector::Vector;
+
+// Interior of a closed object, as seen by the path tracer.
+#[derive(Clone, Copy)]
+pub struct Medium {
+    pub refraction_index: f64,
+    // Where media overlap, the one with the highest priority wins.
+    pub priority: u32,
+    // Absorption and scattering coefficients per unit length.
+    pub absorption: Color,
+    pub scattering: Color,
+    // Henyey-Greenstein asymmetry, 0 scatters isotropically.
+    pub anisotropy: f64,
+}
+
+impl Medium {
+    // A clear medium that only absorbs (Beer-Lambert).
+    pub fn absorbing(refraction_index: f64, priority: u32, absorption: Color) -> Self {
+        Medium {
+            refraction_index,
+            priority,
+            absorption,
+            scattering: Color::zero(),
+            anisotropy: 0.0,
+        }
+    }
+
+    pub fn scatters(&self) -> bool {
+        !self.scattering.near_zero()
+    }
+
+    // Absorption and scattering coefficients, reduced to the path wavelength
+    // on spectral paths.
+    pub fn coefficients(&self, wavelength: Option<f64>) -> (Color, Color) {
+        match wavelength {
+            Some(lambda) => {
+                let a = spectrum::rgb_to_spectrum(self.absorption, lambda);
+                let s = spectrum::rgb_to_spectrum(self.scattering, lambda);
+                (Color::new(a, a, a), Color::new(s, s, s))
+            }
+            None => (self.absorption, self.scattering),
+        }
+    }
+
+    pub fn transmittance(&self, distance: f64, wavelength: Option<f64>) -> Color {
+        let (absorption, scattering) = self.coefficients(wavelength);
+        let mut transmittance = Color::zero();
+        for c in 0..3 {
+            transmittance[c] = (-(absorption[c] + scattering[c]) * distance).exp();
+        }
+        transmittance
+    }
+
+    // Samples a direction from the Henyey-Greenstein phase function around
+    // the direction of propagation.
+    pub fn sample_phase(&self, direction: Vector, u: (f64, f64)) -> Vector {
+        let g = self.anisotropy;
+        let u1 = u.0;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let sq = (1.0 - g * g) / (1.0 + g - 2.0 * g * u1);
+            ((1.0 + g * g - sq * sq) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let frame = Onb::new(direction);
+        frame.transform(Vector::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+}
+
+// Media the current path point is inside of, in the order they were entered.
+// Entries are keyed by the identity of the material that owns them.
+//
+// Overlaps are resolved with the priority scheme of Schmidt and Budge,
+// "Simple Nested Dielectrics in Ray Traced Images" (2002): crossing the
+// boundary of a medium that does not dominate the current point is a false
+// intersection and the ray continues unchanged.
+#[derive(Clone, Default)]
+pub struct MediumStack {
+    entries: Vec<(usize, Medium)>,
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        MediumStack::default()
+    }
+
+    // Highest priority medium, the latest entered one among equals.
+    fn current_entry(&self, skip: Option<usize>) -> Option<&(usize, Medium)> {
+        self.entries
+            .iter()
+            .filter(|(id, _)| Some(*id) != skip)
+            .max_by_key(|(_, m)| m.priority)
+    }
+
+    pub fn current(&self) -> Option<&Medium> {
+        self.current_entry(None).map(|(_, m)| m)
+    }
+
+    // Index of refraction at the current point, 1 outside of every medium.
+    pub fn refraction_index(&self) -> f64 {
+        self.current().map_or(1.0, |m| m.refraction_index)
+    }
+
+    pub fn is_false_hit(&self, id: usize, medium: &Medium, entering: bool) -> bool {
+        match self.current_entry(None) {
+            None => false,
+            Some((current_id, current)) => {
+                if entering {
+                    current.priority > medium.priority
+                } else {
+                    *current_id != id
+                }
+            }
+        }
+    }
+
+    // Index of refraction on the outward normal side of the boundary of
+    // medium `id`.
+    pub fn exterior_index(&self, id: usize, entering: bool) -> f64 {
+        let skip = if entering { None } else { Some(id) };
+        self.current_entry(skip)
+            .map_or(1.0, |(_, m)| m.refraction_index)
+    }
+
+    pub fn push(&mut self, id: usize, medium: Medium) {
+        self.entries.push((id, medium));
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        if let Some(pos) = self.entries.iter().rposition(|(i, _)| *i == id) {
+            self.entries.remove(pos);
+        }
+    }
+
+    // Update after the path crossed the boundary of medium `id`.
+    pub fn cross(&mut self, id: usize, medium: Medium, entering: bool) {
+        if entering {
+            self.push(id, medium);
+        } else {
+            self.remove(id);
+        }
+    }
+}
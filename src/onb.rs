@@ -0,0 +1,44 @@
+use crate::vector::Vector;
+
+// Orthonormal basis with w along the given direction.
+pub struct Onb {
+    axis: [Vector; 3],
+}
+
+impl Onb {
+    pub fn new(n: Vector) -> Self {
+        let w = n.unit();
+        let a = if w.x().abs() > 0.9 {
+            Vector::new(0.0, 1.0, 0.0)
+        } else {
+            Vector::new(1.0, 0.0, 0.0)
+        };
+        let v = Vector::cross(w, a).unit();
+        let u = Vector::cross(w, v);
+        Onb { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vector {
+        self.axis[0]
+    }
+    pub fn v(&self) -> Vector {
+        self.axis[1]
+    }
+    pub fn w(&self) -> Vector {
+        self.axis[2]
+    }
+
+    // Local coordinates to world space.
+    pub fn transform(&self, v: Vector) -> Vector {
+        v[0] * self.axis[0] + v[1] * self.axis[1] + v[2] * self.axis[2]
+    }
+
+    // World space to local coordinates.
+    pub fn to_local(&self, v: Vector) -> Vector {
+        Vector::new(
+            Vector::dot(v, self.axis[0]),
+            Vector::dot(v, self.axis[1]),
+            Vector::dot(v, self.axis[2]),
+        )
+    }
+}
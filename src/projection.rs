@@ -0,0 +1,118 @@
+use crate::rtweeknd::{self, PI};
+use crate::vector::{Point, Vector};
+
+// How the camera maps image positions to rays.
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+    // Pinhole with the camera's vertical field of view.
+    Perspective,
+    // Parallel rays, e.g. for technical drawings. `height` is the extent of
+    // the view in scene units.
+    Orthographic { height: f64 },
+    // Circular fisheye whose image circle touches the top and bottom of the
+    // image and spans `fov` degrees.
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    // Full 360 by 180 degree panorama, optionally omni-directional stereo.
+    Equirectangular { stereo: Option<Stereo> },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FisheyeMapping {
+    // Distance from the center proportional to the angle.
+    Equidistant,
+    // Equal solid angles cover equal image areas.
+    Equisolid,
+}
+
+// Omni-directional stereo (ODS): every ray starts on a circle of diameter
+// `ipd` (interpupillary distance, in scene units) tangent to its direction,
+// as seen by eyes rotating with the viewer's head.
+#[derive(Clone, Copy, Debug)]
+pub struct Stereo {
+    pub ipd: f64,
+    pub eye: Eye,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+    // Left eye in the top half of the image, right eye in the bottom half.
+    OverUnder,
+}
+
+impl Projection {
+    // Ray through film position (x, y) in [0, 1), top left origin, in camera
+    // space: x right, y up and looking down -z. None outside the image circle
+    // of a fisheye.
+    pub fn camera_ray(&self, x: f64, y: f64, aspect: f64, vfov: f64) -> Option<(Point, Vector)> {
+        match *self {
+            Projection::Perspective => {
+                let h = (rtweeknd::deg2rad(vfov) / 2.0).tan();
+                let direction =
+                    Vector::new((2.0 * x - 1.0) * h * aspect, (1.0 - 2.0 * y) * h, -1.0);
+                Some((Point::zero(), direction))
+            }
+            Projection::Orthographic { height } => {
+                let origin = Point::new((x - 0.5) * height * aspect, (0.5 - y) * height, 0.0);
+                Some((origin, Vector::new(0.0, 0.0, -1.0)))
+            }
+            Projection::Fisheye { mapping, fov } => {
+                let px = (2.0 * x - 1.0) * aspect;
+                let py = 1.0 - 2.0 * y;
+                let r = (px * px + py * py).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let half_fov = rtweeknd::deg2rad(fov.clamp(0.0, 360.0)) / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => {
+                        2.0 * (r * (half_fov / 2.0).sin()).clamp(-1.0, 1.0).asin()
+                    }
+                };
+                let phi = py.atan2(px);
+                let direction = Vector::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                );
+                Some((Point::zero(), direction))
+            }
+            Projection::Equirectangular { stereo } => {
+                let (y, eye) = match stereo {
+                    None => (y, None),
+                    Some(Stereo {
+                        eye: Eye::OverUnder,
+                        ipd,
+                    }) => {
+                        if y < 0.5 {
+                            (2.0 * y, Some((Eye::Left, ipd)))
+                        } else {
+                            (2.0 * y - 1.0, Some((Eye::Right, ipd)))
+                        }
+                    }
+                    Some(Stereo { eye, ipd }) => (y, Some((eye, ipd))),
+                };
+                // Longitude 0 looks ahead, latitude is positive up.
+                let longitude = (x - 0.5) * 2.0 * PI;
+                let latitude = (0.5 - y) * PI;
+                let direction = Vector::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                );
+                let origin = match eye {
+                    None => Point::zero(),
+                    Some((eye, ipd)) => {
+                        // Horizontal direction to the right of the ray.
+                        let right = Vector::new(longitude.cos(), 0.0, longitude.sin());
+                        let side = if eye == Eye::Left { -0.5 } else { 0.5 };
+                        side * ipd * right
+                    }
+                };
+                Some((origin, direction))
+            }
+        }
+    }
+}
@@ -4,11 +4,20 @@ use crate::vector::{Point, Vector};
 pub struct Ray {
     origin: Point,
     direction: Vector,
+    // Set when the path carries a single wavelength in nanometers.
+    wavelength: Option<f64>,
+    // Moment within the shutter interval, in seconds after it opened.
+    time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Point, direction: Vector) -> Self {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            wavelength: None,
+            time: 0.0,
+        }
     }
 
     pub fn origin(&self) -> Point {
@@ -18,6 +27,20 @@ impl Ray {
         self.direction
     }
 
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+    pub fn set_wavelength(&mut self, wavelength: Option<f64>) {
+        self.wavelength = wavelength;
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+    pub fn set_time(&mut self, time: f64) {
+        self.time = time;
+    }
+
     pub fn at(&self, t: f64) -> Point {
         self.origin + self.direction * t
     }
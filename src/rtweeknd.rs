@@ -3,7 +3,7 @@ use rand::Rng;
 use crate::interval::Interval;
 
 pub const INF: f64 = f64::INFINITY;
-pub const PI: f64 = 3.1415926535897932385;
+pub const PI: f64 = std::f64::consts::PI;
 
 pub const INTENSITY_INTERVAL: Interval = Interval::intensity_interval();
 
@@ -11,6 +11,10 @@ pub fn deg2rad(degree: f64) -> f64 {
     degree * PI / 180.0
 }
 
+pub fn rad2deg(radian: f64) -> f64 {
+    radian * 180.0 / PI
+}
+
 pub fn random() -> f64 {
     let mut rng = rand::thread_rng();
     rng.gen()
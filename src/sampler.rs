@@ -0,0 +1,508 @@
+use std::sync::OnceLock;
+
+use crate::rtweeknd::{self, PI};
+use crate::vector::{Point, Vector};
+
+// Source of the random numbers consumed along a camera path. A pixel sample
+// draws its numbers one dimension at a time in a fixed order (pixel offset,
+// lens, wavelength, then per bounce BSDF and light samples), so that
+// well distributed sequences line up across the samples of a pixel.
+pub trait Sampler {
+    // Starts sample `index` of pixel (x, y) and rewinds to the first
+    // dimension.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SamplerType {
+    // Uncorrelated uniform random numbers.
+    Independent,
+    // Jittered strata, shuffled independently per dimension.
+    Stratified,
+    // Halton sequence with a random rotation per pixel.
+    Halton,
+    // Owen scrambled Sobol sequence.
+    Sobol,
+    // Per pixel offsets from a blue noise mask, spreading the error as high
+    // frequency noise across the image.
+    BlueNoise,
+}
+
+impl SamplerType {
+    pub fn create(self, samples_per_pixel: u32, seed: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler),
+            SamplerType::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerType::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerType::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerType::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, _x: u32, _y: u32, _index: u32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        rtweeknd::random()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (rtweeknd::random(), rtweeknd::random())
+    }
+}
+
+// State shared by the deterministic samplers.
+#[derive(Clone, Copy, Default)]
+struct SampleState {
+    pixel_seed: u32,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, seed: u32, x: u32, y: u32, index: u32) {
+        self.pixel_seed = hash(hash(x ^ hash(y)) ^ seed);
+        self.x = x;
+        self.y = y;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // Seed of the next dimension, distinct per pixel.
+    fn next_seed(&mut self) -> u32 {
+        let seed = hash(self.pixel_seed ^ hash(self.dimension));
+        self.dimension += 1;
+        seed
+    }
+}
+
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    seed: u32,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u32) -> Self {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(self.seed, x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = self.state.next_seed();
+        let n = self.samples_per_pixel;
+        let stratum = permute(self.state.index % n, n, seed);
+        let jitter = to_unit(hash(seed ^ hash(self.state.index)));
+        (stratum as f64 + jitter) / n as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = self.state.next_seed();
+        // Largest grid that fits in the sample count.
+        let nx = (self.samples_per_pixel as f64).sqrt() as u32;
+        let ny = self.samples_per_pixel / nx;
+        let stratum = permute(self.state.index % (nx * ny), nx * ny, seed);
+        let jitter = hash(seed ^ hash(self.state.index));
+        (
+            ((stratum % nx) as f64 + to_unit(jitter)) / nx as f64,
+            ((stratum / nx) as f64 + to_unit(hash(jitter))) / ny as f64,
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+pub struct HaltonSampler {
+    seed: u32,
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u32) -> Self {
+        HaltonSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(self.seed, x, y, index);
+    }
+
+    // Dimensions past the tabulated primes fall back to random numbers.
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.dimension as usize;
+        let seed = self.state.next_seed();
+        if dimension >= PRIMES.len() {
+            return rtweeknd::random();
+        }
+        // Cranley-Patterson rotation decorrelates neighbouring pixels.
+        let value = radical_inverse(PRIMES[dimension], self.state.index) + to_unit(seed);
+        wrap(value)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Sobol sampler after Burley, "Practical Hash-based Owen Scrambling" (2020).
+// Dimensions are drawn in pairs from the first two Sobol dimensions, with the
+// sample index shuffled per pair so that the pairs are decorrelated
+// (padding), and both the index and the points Owen scrambled by hashing.
+pub struct SobolSampler {
+    seed: u32,
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u32) -> Self {
+        SobolSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(self.seed, x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = self.state.next_seed();
+        let index = nested_uniform_scramble(self.state.index, seed);
+        to_unit(nested_uniform_scramble(
+            sobol(index, 0),
+            hash(seed ^ 0xa511e9b3),
+        ))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = self.state.next_seed();
+        let index = nested_uniform_scramble(self.state.index, seed);
+        (
+            to_unit(nested_uniform_scramble(
+                sobol(index, 0),
+                hash(seed ^ 0xa511e9b3),
+            )),
+            to_unit(nested_uniform_scramble(
+                sobol(index, 1),
+                hash(seed ^ 0x63d83595),
+            )),
+        )
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+// Blue noise dithered rank-1 lattice: every dimension follows a golden ratio
+// (R1) or plastic constant (R2) sequence over the sample index, rotated by a
+// value from a blue noise mask read at a per dimension offset. Neighbouring
+// pixels get very different rotations, which moves the error to high
+// frequencies where it is far less visible at low sample counts.
+pub struct BlueNoiseSampler {
+    seed: u32,
+    state: SampleState,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u32) -> Self {
+        blue_noise_mask();
+        BlueNoiseSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+
+    fn mask_value(&self, seed: u32) -> f64 {
+        let mask = blue_noise_mask();
+        let x = (self.state.x as usize + (seed & 0xffff) as usize) % BLUE_NOISE_SIZE;
+        let y = (self.state.y as usize + (seed >> 16) as usize) % BLUE_NOISE_SIZE;
+        mask[y * BLUE_NOISE_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    // The image seed only moves the mask, the pixel seed would destroy the
+    // blue noise property.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(0, x, y, index);
+        self.state.pixel_seed = hash(self.seed);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = self.state.next_seed();
+        const ALPHA: f64 = 0.618_033_988_749_894_9;
+        wrap(self.mask_value(seed) + self.state.index as f64 * ALPHA)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = self.state.next_seed();
+        // Inverse powers of the plastic constant.
+        const ALPHA: (f64, f64) = (0.754_877_666_246_692_7, 0.569_840_290_998_053_3);
+        let n = self.state.index as f64;
+        (
+            wrap(self.mask_value(seed) + n * ALPHA.0),
+            wrap(self.mask_value(hash(seed)) + n * ALPHA.1),
+        )
+    }
+}
+
+// Tileable blue noise mask of ranks in [0, 1), built once with the
+// void-and-cluster method of Ulichney, "The void-and-cluster method for
+// dither array generation" (1993).
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        const N: usize = BLUE_NOISE_SIZE;
+        const SIGMA: f64 = 1.5;
+        let len = N * N;
+
+        // Gaussian energy filter on the torus, indexed by offset.
+        let mut kernel = vec![0.0; len];
+        for dy in 0..N {
+            for dx in 0..N {
+                let x = dx.min(N - dx) as f64;
+                let y = dy.min(N - dy) as f64;
+                kernel[dy * N + dx] = (-(x * x + y * y) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        let splat = |energy: &mut [f64], p: usize, sign: f64| {
+            let (px, py) = (p % N, p / N);
+            for y in 0..N {
+                for x in 0..N {
+                    let k = kernel[((y + N - py) % N) * N + (x + N - px) % N];
+                    energy[y * N + x] += sign * k;
+                }
+            }
+        };
+        // Tightest cluster among the set points, largest void among the rest.
+        let tightest = |pattern: &[bool], energy: &[f64]| {
+            (0..len)
+                .filter(|&i| pattern[i])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+        let largest_void = |pattern: &[bool], energy: &[f64]| {
+            (0..len)
+                .filter(|&i| !pattern[i])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+
+        // Initial binary pattern: random points relaxed until the tightest
+        // cluster is also the largest void.
+        let mut pattern = vec![false; len];
+        let mut energy = vec![0.0; len];
+        let mut ones = 0;
+        let mut state = 0x9e37_79b9u32;
+        while ones < len / 10 {
+            state = hash(state);
+            let p = state as usize % len;
+            if !pattern[p] {
+                pattern[p] = true;
+                splat(&mut energy, p, 1.0);
+                ones += 1;
+            }
+        }
+        loop {
+            let cluster = tightest(&pattern, &energy);
+            pattern[cluster] = false;
+            splat(&mut energy, cluster, -1.0);
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            splat(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut rank = vec![0; len];
+        // Phase 1: rank the initial points by removing tightest clusters.
+        let mut phase = pattern.clone();
+        let mut phase_energy = energy.clone();
+        for r in (0..ones).rev() {
+            let cluster = tightest(&phase, &phase_energy);
+            phase[cluster] = false;
+            splat(&mut phase_energy, cluster, -1.0);
+            rank[cluster] = r;
+        }
+        // Phases 2 and 3: fill the largest voids until the mask is full.
+        for r in ones..len {
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            splat(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+        rank.iter()
+            .map(|&r| (r as f64 + 0.5) / len as f64)
+            .collect()
+    })
+}
+
+// Integer hash from Wellons' "hash prospector".
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x21f0_aaad);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x735a_2d97);
+    x ^= x >> 15;
+    x
+}
+
+// Number in [0, 1) that only depends on the point, for random choices that
+// have to agree between separate queries about the same surface hit.
+pub fn hash_point(p: Point) -> f64 {
+    let mut h = 0;
+    for i in 0..3 {
+        let bits = p[i].to_bits();
+        h = hash(h ^ bits as u32 ^ (bits >> 32) as u32);
+    }
+    to_unit(h)
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4_294_967_296.0
+}
+
+fn wrap(x: f64) -> f64 {
+    let x = x - x.floor();
+    if x >= 1.0 {
+        0.0
+    } else {
+        x
+    }
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0u64;
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base as u64 + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    (reversed as f64 * inv_base_n).min(1.0 - f64::EPSILON / 2.0)
+}
+
+// Element `i` of a random permutation of 0..n, from Kensler, "Correlated
+// Multi-Jittered Sampling" (2013).
+fn permute(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+// Point `index` of the first two Sobol dimensions as 0.32 fixed point.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut result = 0;
+    let mut v = 1u32 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+// Owen scrambling by hashing, applied to the bits from the most significant
+// down (Laine and Karras, "Stratified sampling for stochastic
+// transparency", 2011).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+// Warps from the unit square.
+
+// Uniform point in the unit disk, z = 0, with Shirley's concentric mapping.
+pub fn concentric_disk(u: (f64, f64)) -> Vector {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vector::zero();
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vector::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+// Uniform direction on the unit sphere.
+pub fn unit_vector(u: (f64, f64)) -> Vector {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Cosine weighted direction around +z.
+pub fn cosine_direction(u: (f64, f64)) -> Vector {
+    let d = concentric_disk(u);
+    let z = (1.0 - d.x() * d.x() - d.y() * d.y()).max(0.0).sqrt();
+    Vector::new(d.x(), d.y(), z)
+}
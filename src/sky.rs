@@ -0,0 +1,258 @@
+use crate::color::Color;
+use crate::light::Light;
+use crate::rtweeknd::{self, PI};
+use crate::vector::Vector;
+
+// Average extraterrestrial luminance of the solar disk in cd/m^2.
+const SUN_LUMINANCE: f64 = 1.9e9;
+
+pub enum Background {
+    // The white to light blue gradient from the book.
+    Gradient,
+    Sky(Box<Sky>),
+}
+
+impl Background {
+    pub fn value(&self, direction: Vector) -> Color {
+        match self {
+            Background::Gradient => {
+                let unit_direction = direction.unit();
+                let a = 0.5 * (unit_direction.y() + 1.0);
+                (1.0 - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0)
+            }
+            Background::Sky(sky) => sky.value(direction),
+        }
+    }
+
+    // Value for paths whose last vertex already sampled the sun as a light.
+    pub fn value_without_sun(&self, direction: Vector) -> Color {
+        match self {
+            Background::Sky(sky) => sky.sky_value(direction),
+            _ => self.value(direction),
+        }
+    }
+
+    // Light the camera samples for the sun, which is too small and bright
+    // to be found by scattered rays.
+    pub fn sun_light(&self) -> Option<Light> {
+        match self {
+            Background::Sky(sky) => sky.sun_light(),
+            _ => None,
+        }
+    }
+}
+
+// Analytic daylight model from Preetham, Shirley and Smits,
+// "A Practical Analytic Model for Daylight" (1999).
+// The world is y-up, azimuth is measured from +z towards +x.
+pub struct Sky {
+    sun_direction: Vector,
+    turbidity: f64,
+    // Multiplier taking luminance in cd/m^2 to scene radiance.
+    pub scale: f64,
+    sun_angular_radius: f64,
+    perez_y: [f64; 5],
+    perez_x: [f64; 5],
+    perez_yy: [f64; 5],
+    zenith: Vector,
+    sun_radiance: Color,
+    cos_sun_radius: f64,
+}
+
+impl Sky {
+    pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64) -> Self {
+        let mut sky = Sky {
+            sun_direction: Vector::new(0.0, 1.0, 0.0),
+            turbidity,
+            // Maps a midday zenith of a few thousand cd/m^2 to roughly 1.
+            scale: 1.0 / 8000.0,
+            // Apparent radius of the sun in degrees.
+            sun_angular_radius: 0.2665,
+            perez_y: [0.0; 5],
+            perez_x: [0.0; 5],
+            perez_yy: [0.0; 5],
+            zenith: Vector::zero(),
+            sun_radiance: Color::zero(),
+            cos_sun_radius: 1.0,
+        };
+        sky.set_sun(sun_elevation, sun_azimuth);
+        sky
+    }
+
+    pub fn sun_direction(&self) -> Vector {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> f64 {
+        self.turbidity
+    }
+
+    pub fn set_turbidity(&mut self, turbidity: f64) {
+        self.turbidity = turbidity;
+        self.update();
+    }
+
+    // Elevation and azimuth are in degrees.
+    pub fn set_sun(&mut self, elevation: f64, azimuth: f64) {
+        let el = rtweeknd::deg2rad(elevation);
+        let az = rtweeknd::deg2rad(azimuth);
+        self.sun_direction = Vector::new(el.cos() * az.sin(), el.sin(), el.cos() * az.cos());
+        self.update();
+    }
+
+    pub fn set_sun_angular_radius(&mut self, degrees: f64) {
+        self.sun_angular_radius = degrees;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let t = self.turbidity;
+        // The model is only valid with the sun above the horizon.
+        let theta_s = self
+            .sun_direction
+            .y()
+            .clamp(0.0, 1.0)
+            .acos()
+            .min(PI / 2.0 - 1e-3);
+
+        self.perez_y = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        self.perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        self.perez_yy = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        // Zenith luminance is given in kcd/m^2.
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * 1000.0;
+        let zenith_x = Self::zenith_chromaticity(
+            t,
+            theta_s,
+            [
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+        );
+        let zenith_yy = Self::zenith_chromaticity(
+            t,
+            theta_s,
+            [
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+        );
+        self.zenith = Vector::new(
+            zenith_y / Self::perez(&self.perez_y, 0.0, theta_s),
+            zenith_x / Self::perez(&self.perez_x, 0.0, theta_s),
+            zenith_yy / Self::perez(&self.perez_yy, 0.0, theta_s),
+        );
+
+        self.cos_sun_radius = rtweeknd::deg2rad(self.sun_angular_radius).cos();
+        self.sun_radiance = if self.sun_direction.y() > 0.0 {
+            SUN_LUMINANCE * Self::sun_transmittance(t, theta_s)
+        } else {
+            Color::zero()
+        };
+    }
+
+    fn zenith_chromaticity(t: f64, theta_s: f64, m: [[f64; 4]; 3]) -> f64 {
+        let ts = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+        let tt = [t * t, t, 1.0];
+        let mut result = 0.0;
+        for (row, tv) in m.iter().zip(tt) {
+            for (coeff, sv) in row.iter().zip(ts) {
+                result += tv * coeff * sv;
+            }
+        }
+        result
+    }
+
+    // Perez et al. sky luminance distribution function.
+    fn perez(c: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+        let cos_theta = theta.cos().max(0.01);
+        (1.0 + c[0] * (c[1] / cos_theta).exp())
+            * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+    }
+
+    // Rayleigh and aerosol (Angstrom) transmittance along the sun path,
+    // evaluated at representative red, green and blue wavelengths.
+    fn sun_transmittance(t: f64, theta_s: f64) -> Color {
+        let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * t - 0.04586;
+        let lambdas = [0.65, 0.55, 0.45];
+        let tau = lambdas.map(|l: f64| {
+            let rayleigh = (-0.008735 * l.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * l.powf(-1.3) * air_mass).exp();
+            rayleigh * aerosol
+        });
+        Color::new(tau[0], tau[1], tau[2])
+    }
+
+    pub fn sky_value(&self, direction: Vector) -> Color {
+        let dir = direction.unit();
+        // Below the horizon we keep the horizon color.
+        let up = Vector::new(dir.x(), dir.y().max(0.0), dir.z()).unit();
+        let theta = up.y().clamp(-1.0, 1.0).acos();
+        let gamma = Vector::dot(up, self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let luminance = self.zenith.x() * Self::perez(&self.perez_y, theta, gamma);
+        let x = self.zenith.y() * Self::perez(&self.perez_x, theta, gamma);
+        let y = self.zenith.z() * Self::perez(&self.perez_yy, theta, gamma);
+
+        xyy_to_rgb(x, y, luminance.max(0.0)) * self.scale
+    }
+
+    pub fn sun_value(&self, direction: Vector) -> Color {
+        if Vector::dot(direction.unit(), self.sun_direction) >= self.cos_sun_radius {
+            self.sun_radiance * self.scale
+        } else {
+            Color::zero()
+        }
+    }
+
+    pub fn value(&self, direction: Vector) -> Color {
+        self.sky_value(direction) + self.sun_value(direction)
+    }
+
+    // The sun disk as a directional light of the same irradiance, its
+    // radiance times the solid angle of the disk. None below the horizon.
+    pub fn sun_light(&self) -> Option<Light> {
+        if self.sun_direction.y() <= 0.0 {
+            return None;
+        }
+        let solid_angle = 2.0 * PI * (1.0 - self.cos_sun_radius);
+        Some(Light::Directional {
+            direction: -self.sun_direction,
+            irradiance: self.sun_radiance * self.scale * solid_angle,
+        })
+    }
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::zero();
+    }
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    let r = 3.2406 * cx - 1.5372 * luminance - 0.4986 * cz;
+    let g = -0.9689 * cx + 1.8758 * luminance + 0.0415 * cz;
+    let b = 0.0557 * cx - 0.2040 * luminance + 1.0570 * cz;
+    Color::new(r.max(0.0), g.max(0.0), b.max(0.0))
+}
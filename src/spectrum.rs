@@ -0,0 +1,132 @@
+use std::sync::OnceLock;
+
+use crate::color::Color;
+use crate::vector::Vector;
+
+// Visible range sampled by the spectral renderer, in nanometers.
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+// Basis spectra from Smits, "An RGB to Spectrum Conversion for Reflectances"
+// (1999), tabulated in ten equal bins over [LAMBDA_MIN, LAMBDA_MAX].
+const WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// Maps a uniform random number to a wavelength. The pdf is constant.
+pub fn sample_wavelength(u: f64) -> f64 {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+fn basis(table: &[f64; 10], lambda: f64) -> f64 {
+    let x = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0;
+    table[(x.max(0.0) as usize).min(9)]
+}
+
+// Value at `lambda` of a smooth spectrum that reproduces the RGB color.
+pub fn rgb_to_spectrum(c: Color, lambda: f64) -> f64 {
+    let (r, g, b) = (c.x(), c.y(), c.z());
+    let mut result = 0.0;
+    if r <= g && r <= b {
+        result += r * basis(&WHITE, lambda);
+        if g <= b {
+            result += (g - r) * basis(&CYAN, lambda) + (b - g) * basis(&BLUE, lambda);
+        } else {
+            result += (b - r) * basis(&CYAN, lambda) + (g - b) * basis(&GREEN, lambda);
+        }
+    } else if g <= r && g <= b {
+        result += g * basis(&WHITE, lambda);
+        if r <= b {
+            result += (r - g) * basis(&MAGENTA, lambda) + (b - r) * basis(&BLUE, lambda);
+        } else {
+            result += (b - g) * basis(&MAGENTA, lambda) + (r - b) * basis(&RED, lambda);
+        }
+    } else {
+        result += b * basis(&WHITE, lambda);
+        if r <= g {
+            result += (r - b) * basis(&YELLOW, lambda) + (g - r) * basis(&GREEN, lambda);
+        } else {
+            result += (g - b) * basis(&YELLOW, lambda) + (r - g) * basis(&RED, lambda);
+        }
+    }
+    result.max(0.0)
+}
+
+fn lobe(x: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 color matching functions using the multi-lobe fit of Wyman,
+// Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color
+// Matching Functions" (2013).
+pub fn cie_xyz(lambda: f64) -> Vector {
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    Vector::new(x, y, z)
+}
+
+// CIE XYZ to linear sRGB (D65).
+pub fn xyz_to_rgb(xyz: Vector) -> Color {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Color::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+struct Normalization {
+    // Integral of the y matching function over the sampled range.
+    y_integral: f64,
+    // Per channel correction so that a flat spectrum maps to RGB white.
+    white: Color,
+}
+
+fn normalization() -> &'static Normalization {
+    static NORMALIZATION: OnceLock<Normalization> = OnceLock::new();
+    NORMALIZATION.get_or_init(|| {
+        let steps = 1000;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let mut xyz = Vector::zero();
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) * dl;
+            xyz += cie_xyz(lambda) * dl;
+        }
+        let y_integral = xyz.y();
+        let flat = xyz_to_rgb(xyz / y_integral);
+        Normalization {
+            y_integral,
+            white: Color::new(1.0 / flat.x(), 1.0 / flat.y(), 1.0 / flat.z()),
+        }
+    })
+}
+
+// Converts a single wavelength sample of spectral radiance, drawn with
+// `sample_wavelength`, to a linear RGB estimate.
+pub fn to_rgb(value: f64, lambda: f64) -> Color {
+    let norm = normalization();
+    let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+    let xyz = cie_xyz(lambda) * (value / (pdf * norm.y_integral));
+    xyz_to_rgb(xyz) * norm.white
+}
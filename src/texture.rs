@@ -0,0 +1,158 @@
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::color::{self, Color};
+use crate::colorspace::{self, ColorSpace};
+use crate::image::Image;
+use crate::vector::Point;
+
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: Point) -> Color;
+
+    // Scalar parameters read the first channel.
+    fn scalar(&self, u: f64, v: f64, p: Point) -> f64 {
+        self.value(u, v, p).x()
+    }
+}
+
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        SolidColor { albedo }
+    }
+
+    pub fn from_scalar(value: f64) -> Self {
+        SolidColor::new(Color::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point) -> Color {
+        self.albedo
+    }
+}
+
+pub fn constant(value: f64) -> Rc<dyn Texture> {
+    Rc::new(SolidColor::from_scalar(value))
+}
+
+pub fn solid(albedo: Color) -> Rc<dyn Texture> {
+    Rc::new(SolidColor::new(albedo))
+}
+
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Rc<dyn Texture>,
+    odd: Rc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Rc<dyn Texture>, odd: Rc<dyn Texture>) -> Self {
+        CheckerTexture {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Point) -> Color {
+        let x = (self.inv_scale * p.x()).floor() as i64;
+        let y = (self.inv_scale * p.y()).floor() as i64;
+        let z = (self.inv_scale * p.z()).floor() as i64;
+        if (x + y + z) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl ImageTexture {
+    // Color images are stored sRGB encoded and converted to linear on load.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut image = Image::load(path)?;
+        image.map(|c| {
+            Color::new(
+                color::srgb_to_linear(c.x()),
+                color::srgb_to_linear(c.y()),
+                color::srgb_to_linear(c.z()),
+            )
+        });
+        Ok(ImageTexture { image })
+    }
+
+    // For non-color data such as roughness or normal maps.
+    pub fn new_linear<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(ImageTexture {
+            image: Image::load(path)?,
+        })
+    }
+
+    pub fn from_image(image: Image) -> Self {
+        ImageTexture { image }
+    }
+
+    // Converts the sRGB colors of the image to a wider working space.
+    pub fn in_space(mut self, space: ColorSpace) -> Self {
+        let m = ColorSpace::Srgb.conversion(space);
+        self.image.map(|c| colorspace::mul(&m, c));
+        self
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point) -> Color {
+        if self.image.width() == 0 || self.image.height() == 0 {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+        // Wrap around and flip v so that v = 0 is the bottom of the image.
+        let u = u - u.floor();
+        let v = 1.0 - (v - v.floor());
+
+        // Bilinear filtering between pixel centers.
+        let x = u * self.image.width() as f64 - 0.5;
+        let y = v * self.image.height() as f64 - 0.5;
+        let x0 = x.floor().max(0.0);
+        let y0 = y.floor().max(0.0);
+        let tx = (x - x0).clamp(0.0, 1.0);
+        let ty = (y - y0).clamp(0.0, 1.0);
+        let (i, j) = (x0 as usize, y0 as usize);
+
+        let top = (1.0 - tx) * self.image.pixel(i, j) + tx * self.image.pixel(i + 1, j);
+        let bottom = (1.0 - tx) * self.image.pixel(i, j + 1) + tx * self.image.pixel(i + 1, j + 1);
+        (1.0 - ty) * top + ty * bottom
+    }
+}
+
+// Selects a single channel of another texture, e.g. to read roughness from
+// the green and metallic from the blue channel of a packed glTF texture.
+pub struct ChannelTexture {
+    source: Rc<dyn Texture>,
+    channel: usize,
+}
+
+impl ChannelTexture {
+    pub fn new(source: Rc<dyn Texture>, channel: usize) -> Self {
+        ChannelTexture {
+            source,
+            channel: channel.min(2),
+        }
+    }
+}
+
+impl Texture for ChannelTexture {
+    fn value(&self, u: f64, v: f64, p: Point) -> Color {
+        let c = self.source.value(u, v, p)[self.channel];
+        Color::new(c, c, c)
+    }
+}
@@ -0,0 +1,160 @@
+use std::ops;
+use std::rc::Rc;
+
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::rtweeknd::PI;
+use crate::texture::Texture;
+
+// Wavelengths in nanometers standing in for the RGB channels.
+const RGB_WAVELENGTHS: [f64; 3] = [650.0, 532.0, 450.0];
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    fn real(re: f64) -> Self {
+        Complex::new(re, 0.0)
+    }
+
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Self {
+        let r = self.norm_sqr().sqrt();
+        let re = ((r + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.re) / 2.0).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(self) -> Self {
+        let m = self.re.exp();
+        Complex::new(m * self.im.cos(), m * self.im.sin())
+    }
+}
+
+impl ops::Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+impl ops::Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+impl ops::Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+impl ops::Div for Complex {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        let d = rhs.norm_sqr();
+        Complex::new(
+            (self.re * rhs.re + self.im * rhs.im) / d,
+            (self.im * rhs.re - self.re * rhs.im) / d,
+        )
+    }
+}
+
+// A thin transparent film on top of a surface, producing interference colors
+// like soap bubbles, oil slicks or anodized metal.
+pub struct ThinFilm {
+    // Film thickness in nanometers, read from the first channel.
+    pub thickness: Rc<dyn Texture>,
+    pub refraction_index: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: Rc<dyn Texture>, refraction_index: f64) -> Self {
+        ThinFilm {
+            thickness,
+            refraction_index,
+        }
+    }
+
+    // Reflectance of the film stack between an incident medium of index
+    // `outer` and a substrate of complex index eta + ik. Evaluated at the
+    // path wavelength when there is one, per RGB channel otherwise.
+    pub fn reflectance(
+        &self,
+        rec: &HitRecord,
+        cos_i: f64,
+        outer: f64,
+        eta: Color,
+        k: Color,
+        wavelength: Option<f64>,
+    ) -> Color {
+        let thickness = self.thickness.scalar(rec.u, rec.v, rec.p).max(0.0);
+        let mut result = Color::zero();
+        for c in 0..3 {
+            let lambda = wavelength.unwrap_or(RGB_WAVELENGTHS[c]);
+            result[c] = airy_reflectance(
+                cos_i,
+                outer,
+                self.refraction_index,
+                Complex::new(eta[c], k[c]),
+                thickness,
+                lambda,
+            );
+        }
+        result
+    }
+}
+
+// Interface amplitude coefficients for s and p polarization.
+fn fresnel_amplitudes(
+    n_i: Complex,
+    cos_i: Complex,
+    n_t: Complex,
+    cos_t: Complex,
+) -> (Complex, Complex) {
+    let rs = (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t);
+    let rp = (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t);
+    (rs, rp)
+}
+
+// Cosine of the transmitted angle from Snell's law, complex when the wave is
+// evanescent or the medium absorbs.
+fn transmitted_cos(n_i: Complex, n_t: Complex, sin2_i: f64) -> Complex {
+    let ratio = n_i / n_t;
+    (Complex::real(1.0) - ratio * ratio * Complex::real(sin2_i)).sqrt()
+}
+
+// Airy summation of the multiple reflections inside a single film layer.
+fn airy_reflectance(cos_i: f64, n1: f64, n2: f64, n3: Complex, thickness: f64, lambda: f64) -> f64 {
+    let cos1 = cos_i.clamp(0.0, 1.0);
+    let sin2 = 1.0 - cos1 * cos1;
+    let (n1, n2) = (Complex::real(n1), Complex::real(n2));
+    let cos1 = Complex::real(cos1);
+    let cos2 = transmitted_cos(n1, n2, sin2);
+    let cos3 = transmitted_cos(n1, n3, sin2);
+
+    let (r12s, r12p) = fresnel_amplitudes(n1, cos1, n2, cos2);
+    let (r23s, r23p) = fresnel_amplitudes(n2, cos2, n3, cos3);
+
+    // Round trip phase difference through the film.
+    let delta = Complex::real(4.0 * PI * thickness / lambda) * n2 * cos2;
+    let phase = (Complex::new(0.0, 1.0) * delta).exp();
+
+    let one = Complex::real(1.0);
+    let rs = (r12s + r23s * phase) / (one + r12s * r23s * phase);
+    let rp = (r12p + r23p * phase) / (one + r12p * r23p * phase);
+    (0.5 * (rs.norm_sqr() + rp.norm_sqr())).clamp(0.0, 1.0)
+}
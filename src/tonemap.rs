@@ -0,0 +1,330 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::color::{self, Color};
+use crate::colorspace::mul;
+
+// Maps scene linear radiance in linear sRGB (Rec. 709 primaries) to display
+// linear values in [0, 1].
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ToneMapper {
+    // Values above 1 are clipped.
+    #[default]
+    Clip,
+    // L / (1 + L) on the luminance, keeping hue.
+    Reinhard,
+    // Reinhard that maps the luminance `white` to 1 instead of infinity.
+    ExtendedReinhard {
+        white: f64,
+    },
+    // John Hable's filmic curve from Uncharted 2.
+    Hable,
+    // Stephen Hill's fit of the ACES reference rendering and sRGB output
+    // transforms.
+    Aces,
+    // Troy Sobotka's AgX, which desaturates bright colors towards white
+    // instead of skewing their hue.
+    AgX,
+}
+
+impl ToneMapper {
+    pub fn apply(&self, c: Color) -> Color {
+        match *self {
+            ToneMapper::Clip => c,
+            ToneMapper::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMapper::ExtendedReinhard { white } => {
+                scale_luminance(c, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapper::Hable => {
+                const WHITE: f64 = 11.2;
+                const EXPOSURE_BIAS: f64 = 2.0;
+                let white_scale = 1.0 / hable(WHITE);
+                Color::new(
+                    hable(EXPOSURE_BIAS * c.x()) * white_scale,
+                    hable(EXPOSURE_BIAS * c.y()) * white_scale,
+                    hable(EXPOSURE_BIAS * c.z()) * white_scale,
+                )
+            }
+            ToneMapper::Aces => {
+                const INPUT: [[f64; 3]; 3] = [
+                    [0.59719, 0.35458, 0.04823],
+                    [0.07600, 0.90834, 0.01566],
+                    [0.02840, 0.13383, 0.83777],
+                ];
+                const OUTPUT: [[f64; 3]; 3] = [
+                    [1.60475, -0.53108, -0.07367],
+                    [-0.10208, 1.10813, -0.00605],
+                    [-0.00327, -0.07276, 1.07602],
+                ];
+                let v = mul(&INPUT, c);
+                let fit = |v: f64| {
+                    (v * (v + 0.0245786) - 0.000090537)
+                        / (v * (0.983729 * v + 0.4329510) + 0.238081)
+                };
+                mul(&OUTPUT, Color::new(fit(v.x()), fit(v.y()), fit(v.z())))
+            }
+            ToneMapper::AgX => {
+                // Inset into a smaller gamut, then a sigmoid on log2 values
+                // between MIN_EV and MAX_EV stops around middle grey.
+                const INSET: [[f64; 3]; 3] = [
+                    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+                    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+                    [0.0423756549057051, 0.0784336, 0.879142973793104],
+                ];
+                const OUTSET: [[f64; 3]; 3] = [
+                    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+                    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+                    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+                ];
+                const MIN_EV: f64 = -12.47393;
+                const MAX_EV: f64 = 4.026069;
+                let v = mul(&INSET, c);
+                let curve = |x: f64| {
+                    let x =
+                        (x.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+                    agx_contrast(x)
+                };
+                let v = mul(
+                    &OUTSET,
+                    Color::new(curve(v.x()), curve(v.y()), curve(v.z())),
+                );
+                // The curve gives display encoded values.
+                Color::new(
+                    color::srgb_to_linear(v.x().max(0.0)),
+                    color::srgb_to_linear(v.y().max(0.0)),
+                    color::srgb_to_linear(v.z().max(0.0)),
+                )
+            }
+        }
+    }
+}
+
+fn scale_luminance(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    let l = color::luminance(c);
+    if l <= 0.0 {
+        return Color::zero();
+    }
+    c * (f(l) / l)
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+// Polynomial fit of the AgX base contrast sigmoid.
+fn agx_contrast(x: f64) -> f64 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232
+}
+
+// Largest LUT_3D_SIZE the .cube specification allows.
+const MAX_LUT_SIZE: usize = 256;
+
+// 3D lookup table in the Resolve/Adobe .cube format, e.g. a creative look.
+// Like most published looks it is applied to sRGB encoded values.
+pub struct Lut {
+    size: usize,
+    domain_min: Color,
+    domain_max: Color,
+    // size^3 entries, red changing fastest.
+    table: Vec<Color>,
+}
+
+impl Lut {
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut size = 0;
+        let mut domain_min = Color::zero();
+        let mut domain_max = Color::new(1.0, 1.0, 1.0);
+        let mut table = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let parse_color = |words: std::str::SplitWhitespace| {
+                let values = words
+                    .map(|v| v.parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()
+                    .filter(|v| v.len() == 3);
+                values
+                    .map(|v| Color::new(v[0], v[1], v[2]))
+                    .ok_or_else(|| invalid(format!("line {}: expected 3 numbers", number + 1)))
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    // The file is untrusted, the size must not overflow the
+                    // entry count or allocate without bound.
+                    size = words
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|n| (2..=MAX_LUT_SIZE).contains(n))
+                        .ok_or_else(|| invalid(format!("line {}: bad LUT size", number + 1)))?;
+                }
+                "LUT_1D_SIZE" => return Err(invalid("1D LUTs are not supported".to_string())),
+                "DOMAIN_MIN" => domain_min = parse_color(words)?,
+                "DOMAIN_MAX" => domain_max = parse_color(words)?,
+                _ => table.push(parse_color(line.split_whitespace())?),
+            }
+        }
+        if size == 0 {
+            return Err(invalid("missing LUT_3D_SIZE".to_string()));
+        }
+        let count = size
+            .checked_pow(3)
+            .ok_or_else(|| invalid("LUT too large".to_string()))?;
+        if table.len() != count {
+            return Err(invalid(format!(
+                "expected {} entries, found {}",
+                count,
+                table.len()
+            )));
+        }
+        Ok(Lut {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Lut::parse(&fs::read_to_string(path)?)
+    }
+
+    // Trilinear interpolation of the table.
+    pub fn apply(&self, c: Color) -> Color {
+        let n = self.size - 1;
+        let mut index = [0; 3];
+        let mut fraction = [0.0; 3];
+        for i in 0..3 {
+            let range = self.domain_max[i] - self.domain_min[i];
+            let t = if range > 0.0 {
+                ((c[i] - self.domain_min[i]) / range).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let x = t * n as f64;
+            index[i] = (x as usize).min(n - 1);
+            fraction[i] = x - index[i] as f64;
+        }
+        let mut result = Color::zero();
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut entry = 0;
+            let mut stride = 1;
+            for i in 0..3 {
+                let upper = (corner >> i) & 1;
+                weight *= if upper == 1 {
+                    fraction[i]
+                } else {
+                    1.0 - fraction[i]
+                };
+                entry += (index[i] + upper) * stride;
+                stride *= self.size;
+            }
+            result += weight * self.table[entry];
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // .cube table mapping every grid point to itself over the domain.
+    fn identity_cube(size: usize, min: f64, max: f64) -> String {
+        let mut text = format!(
+            "TITLE \"identity\"\n# comment\nLUT_3D_SIZE {}\nDOMAIN_MIN {} {} {}\nDOMAIN_MAX {} {} {}\n",
+            size, min, min, min, max, max, max
+        );
+        let value = |i: usize| min + (max - min) * i as f64 / (size - 1) as f64;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    text += &format!("{} {} {}\n", value(r), value(g), value(b));
+                }
+            }
+        }
+        text
+    }
+
+    fn close(a: Color, b: Color) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn identity_lut() {
+        let lut = Lut::parse(&identity_cube(17, 0.0, 1.0)).unwrap();
+        for c in [
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(0.25, 0.5, 0.75),
+            Color::new(0.123, 0.987, 0.5),
+            Color::new(1.0, 1.0, 1.0),
+        ] {
+            assert!(close(lut.apply(c), c));
+        }
+        // Values outside the domain are clamped to its edge.
+        assert!(close(
+            lut.apply(Color::new(-0.5, 2.0, 0.5)),
+            Color::new(0.0, 1.0, 0.5)
+        ));
+    }
+
+    #[test]
+    fn lut_domain() {
+        let lut = Lut::parse(&identity_cube(3, -1.0, 3.0)).unwrap();
+        for c in [Color::new(-1.0, 0.0, 3.0), Color::new(2.5, -0.3, 1.7)] {
+            assert!(close(lut.apply(c), c));
+        }
+    }
+
+    #[test]
+    fn lut_errors() {
+        let mut short = identity_cube(2, 0.0, 1.0);
+        short.truncate(short.trim_end().rfind('\n').unwrap());
+        let err = Lut::parse(&short).err().unwrap();
+        assert!(err.to_string().contains("expected 8 entries, found 7"));
+        assert!(Lut::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(Lut::parse("0 0 0\n").is_err());
+        assert!(Lut::parse("LUT_3D_SIZE 2\n0 0\n").is_err());
+        let err = Lut::parse("LUT_3D_SIZE 3000000\n0 0 0\n").err().unwrap();
+        assert!(err.to_string().contains("bad LUT size"));
+    }
+
+    #[test]
+    fn tone_mappers() {
+        let grey = Color::new(1.0, 1.0, 1.0);
+        assert!(close(ToneMapper::Clip.apply(grey), grey));
+        assert!(close(ToneMapper::Reinhard.apply(grey), 0.5 * grey));
+        let white = ToneMapper::ExtendedReinhard { white: 4.0 };
+        assert!(close(white.apply(4.0 * grey), grey));
+        // Hable maps its white point, 11.2 before the exposure bias of 2, to 1.
+        assert!(close(ToneMapper::Hable.apply(5.6 * grey), grey));
+        for mapper in [ToneMapper::Hable, ToneMapper::Aces, ToneMapper::AgX] {
+            let mut previous = -1.0;
+            for i in 0..100 {
+                let l = color::luminance(mapper.apply(0.01 * (1.1f64).powi(i) * grey));
+                assert!(l >= previous);
+                previous = l;
+            }
+        }
+        for mapper in [ToneMapper::Aces, ToneMapper::AgX] {
+            assert!(color::luminance(mapper.apply(1e4 * grey)) < 1.02);
+        }
+    }
+}
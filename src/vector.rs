@@ -3,7 +3,7 @@ use std::ops::{self, MulAssign};
 
 use crate::rtweeknd;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vector {
     e: [f64; 3],
 }
@@ -75,9 +75,9 @@ impl Vector {
     pub fn random_on_hemisphere(normal: &Vector) -> Self {
         let on_unit_sphere = Self::random_unit_vector();
         if Vector::dot(on_unit_sphere, *normal) > 0.0 {
-            return on_unit_sphere;
+            on_unit_sphere
         } else {
-            return -on_unit_sphere;
+            -on_unit_sphere
         }
     }
 